@@ -1,17 +1,23 @@
 extern crate downcast_rs;
 extern crate lazy_static;
 
-use crate::lexer::Lexer;
+use crate::lexer::{split_template, Lexer, TemplateChunk};
 use crate::program::ProgramNode;
 use crate::statements::{
-    BlockStatement, BooleanExpression, CallExpression, ExpressionStatement,
-    FunctionLiteralExpression, IdentifierExpression, IfExpression, InfixExpression,
-    IntegerLiteralExpression, LetStatement, PrefixExpression, ReturnStatement,
-    StringLiteralExpression,
+    ArrayLiteralExpression, BlockExpression, BlockStatement, BooleanExpression, CallExpression,
+    ExpressionStatement, FunctionLiteralExpression, GroupedExpression, HashLiteralExpression,
+    IdentifierExpression, IfExpression, ImportStatement, IndexExpression, InfixExpression,
+    IntegerLiteralExpression, LetInExpression, LetStatement, MatchArm, MatchExpression,
+    MatchPattern, PrefixExpression, RestParameterExpression, ReturnStatement,
+    StringLiteralExpression, TemplateExpression, TemplatePart, ThrowExpression, TryExpression,
+    WhileExpression,
 };
 use crate::token::{Token, TokenType};
 use lazy_static::lazy_static;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
 
 /////////////////
 // Precendences //
@@ -20,25 +26,39 @@ use std::collections::HashMap;
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum PrecedenceType {
     LOWEST = 0,
-    EQUALS = 1,
-    LESSGREATER = 2,
-    SUM = 3,
-    PRODUCT = 4,
-    PREFIX = 5,
-    CALL = 6,
+    PIPE = 1,
+    COALESCE = 2,
+    OR = 3,
+    AND = 4,
+    EQUALS = 5,
+    LESSGREATER = 6,
+    SUM = 7,
+    PRODUCT = 8,
+    PREFIX = 9,
+    POWER = 10,
+    CALL = 11,
+    INDEX = 12,
 }
 
 lazy_static! {
     static ref PRECEDENCE_MAP: HashMap<TokenType, PrecedenceType> = HashMap::from([
+        (TokenType::PIPE, PrecedenceType::PIPE),
+        (TokenType::COALESCE, PrecedenceType::COALESCE),
+        (TokenType::OR, PrecedenceType::OR),
+        (TokenType::AND, PrecedenceType::AND),
         (TokenType::EQ, PrecedenceType::EQUALS),
         (TokenType::NEQ, PrecedenceType::EQUALS),
         (TokenType::LT, PrecedenceType::LESSGREATER),
         (TokenType::GT, PrecedenceType::LESSGREATER),
+        (TokenType::LTE, PrecedenceType::LESSGREATER),
+        (TokenType::GTE, PrecedenceType::LESSGREATER),
         (TokenType::PLUS, PrecedenceType::SUM),
         (TokenType::MINUS, PrecedenceType::SUM),
         (TokenType::SLASH, PrecedenceType::PRODUCT),
         (TokenType::ASTERISK, PrecedenceType::PRODUCT),
-        (TokenType::LPAREN, PrecedenceType::CALL)
+        (TokenType::POWER, PrecedenceType::POWER),
+        (TokenType::LPAREN, PrecedenceType::CALL),
+        (TokenType::LBRACKET, PrecedenceType::INDEX)
     ]);
 }
 
@@ -46,11 +66,41 @@ lazy_static! {
 // Parser //
 ////////////
 
+// How a token is shown in a parse trace, e.g. `*` or EOF.
+fn describe_token(token: &Token) -> String {
+    if token.literal.is_some() {
+        return format!("`{}`", token.literal.as_ref().unwrap());
+    }
+    return format!("{:?}", token.token_type);
+}
+
+thread_local! {
+    // Set while `try_parse` is running, so the panics it catches aren't printed
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+static QUIET_PANIC_HOOK: Once = Once::new();
+
+// The message a panic was raised with, e.g. `INVALID HASH LITERAL`.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    let message = payload.downcast_ref::<&str>();
+    if message.is_some() {
+        return message.unwrap().to_string();
+    }
+    let message = payload.downcast_ref::<String>();
+    if message.is_some() {
+        return message.unwrap().clone();
+    }
+    return "invalid syntax".to_string();
+}
+
 pub struct Parser {
     lexer: Lexer,
     current_token: Token,
     peek_token: Token,
     errors: Vec<String>,
+    // Parse decisions, only recorded by `parse_with_trace`
+    trace: Option<Vec<String>>,
 }
 
 impl Parser {
@@ -63,6 +113,7 @@ impl Parser {
             current_token,
             peek_token,
             errors: vec![],
+            trace: None,
         };
 
         return parser;
@@ -74,19 +125,11 @@ impl Parser {
     }
 
     fn current_token_is(&self, token_type: &TokenType) -> bool {
-        if &self.current_token.token_type == token_type {
-            return true;
-        } else {
-            return false;
-        }
+        return &self.current_token.token_type == token_type;
     }
 
     fn peek_token_is(&self, token_type: &TokenType) -> bool {
-        if &self.peek_token.token_type == token_type {
-            return true;
-        } else {
-            return false;
-        }
+        return &self.peek_token.token_type == token_type;
     }
 
     fn expect_peek(&mut self, token_type: &TokenType) -> bool {
@@ -96,7 +139,7 @@ impl Parser {
         } else {
             let msg = format!(
                 "Expected next token to be {:?}, got {:?} instead",
-                &self.peek_token.token_type, token_type
+                token_type, &self.peek_token.token_type
             );
             self.errors.push(msg);
             return false;
@@ -131,11 +174,54 @@ impl Parser {
         return statements;
     }
 
+    // Like `parse`, but invalid input gives back the error and the byte offset
+    // of the token it was found at instead of panicking.
+    pub fn try_parse(&mut self) -> Result<Vec<Box<dyn ProgramNode>>, (String, usize)> {
+        QUIET_PANIC_HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if !CATCHING_PANICS.with(|catching| catching.get()) {
+                    default_hook(info);
+                }
+            }));
+        });
+
+        CATCHING_PANICS.with(|catching| catching.set(true));
+        let parsed = panic::catch_unwind(AssertUnwindSafe(|| self.parse()));
+        CATCHING_PANICS.with(|catching| catching.set(false));
+        if parsed.is_ok() {
+            return Ok(parsed.ok().unwrap());
+        }
+
+        // A missing token is described better than by the panic that follows
+        if self.errors.len() > 0 {
+            return Err((self.errors.pop().unwrap(), self.peek_token.span.0));
+        }
+        let message = panic_message(parsed.err().unwrap().as_ref());
+        return Err((message, self.current_token.span.0));
+    }
+
+    // Like `parse`, also returning each prefix and infix parse and the
+    // precedence comparison that led to it, in the order they happened.
+    pub fn parse_with_trace(&mut self) -> (Vec<Box<dyn ProgramNode>>, Vec<String>) {
+        self.trace = Some(vec![]);
+        let statements = self.parse();
+        return (statements, self.trace.take().unwrap());
+    }
+
+    fn record(&mut self, event: impl FnOnce() -> String) {
+        if self.trace.is_some() {
+            self.trace.as_mut().unwrap().push(event());
+        }
+    }
+
     fn parse_statement(&mut self) -> Box<dyn ProgramNode> {
         let token_type = self.current_token.token_type;
         let statement = match token_type {
             TokenType::LET => self.parse_let_statement(),
+            TokenType::CONST => self.parse_let_statement(),
             TokenType::RETURN => self.parse_return_statement(),
+            TokenType::IMPORT => self.parse_import_statement(),
             TokenType::INT => self.parse_expression_statement(),
             TokenType::BANG => self.parse_expression_statement(),
             TokenType::MINUS => self.parse_expression_statement(),
@@ -144,8 +230,16 @@ impl Parser {
             TokenType::FALSE => self.parse_expression_statement(),
             TokenType::LPAREN => self.parse_expression_statement(),
             TokenType::STRING => self.parse_expression_statement(),
+            TokenType::TEMPLATE => self.parse_expression_statement(),
             TokenType::IF => self.parse_expression_statement(),
+            TokenType::WHILE => self.parse_expression_statement(),
+            TokenType::DO => self.parse_expression_statement(),
+            TokenType::TRY => self.parse_expression_statement(),
+            TokenType::THROW => self.parse_expression_statement(),
+            TokenType::MATCH => self.parse_expression_statement(),
             TokenType::FUNCTION => self.parse_expression_statement(),
+            TokenType::LBRACE => self.parse_expression_statement(),
+            TokenType::LBRACKET => self.parse_expression_statement(),
             _ => panic!("PANIC!"),
         };
 
@@ -170,22 +264,84 @@ impl Parser {
             self.next_token();
         }
 
-        return Box::new(LetStatement::new(
-            og_token,
-            Box::new(name),
-            self.parse_expression(PrecedenceType::LOWEST),
-        ));
+        let value = self.parse_expression(PrecedenceType::LOWEST);
+        if self.peek_token_is(&TokenType::IN) {
+            if og_token.token_type == TokenType::CONST {
+                panic!("CONST CANNOT BE USED WITH IN");
+            }
+            let let_in = self.parse_let_in_body(og_token.clone(), Box::new(name), value);
+            return Box::new(ExpressionStatement::new(og_token, let_in));
+        }
+
+        return Box::new(LetStatement::new(og_token, Box::new(name), value));
+    }
+
+    // `let x = v in expr` in expression position.
+    fn parse_let_in_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        if !self.expect_peek(&TokenType::IDENT) {
+            panic!("INVALID LET IN EXPRESSION");
+        }
+        let name = IdentifierExpression {
+            token: self.current_token.clone(),
+            value: self.current_token.clone().literal.unwrap(),
+        };
+
+        if !self.expect_peek(&TokenType::ASSIGN) {
+            panic!("INVALID LET IN EXPRESSION");
+        }
+        self.next_token();
+
+        let value = self.parse_expression(PrecedenceType::LOWEST);
+        return self.parse_let_in_body(og_token, Box::new(name), value);
+    }
+
+    fn parse_let_in_body(
+        &mut self,
+        og_token: Token,
+        name: Box<dyn ProgramNode>,
+        value: Box<dyn ProgramNode>,
+    ) -> Box<dyn ProgramNode> {
+        if !self.expect_peek(&TokenType::IN) {
+            panic!("LET EXPRESSION MUST BE FOLLOWED BY IN");
+        }
+        self.next_token();
+
+        let body = self.parse_expression(PrecedenceType::LOWEST);
+        return Box::new(LetInExpression::new(og_token, name, value, body));
     }
     fn parse_return_statement(&mut self) -> Box<dyn ProgramNode> {
         let og_token = self.current_token.clone();
         self.next_token();
 
+        let value = self.parse_expression(PrecedenceType::LOWEST);
+        if !self.peek_token_is(&TokenType::COMMA) {
+            return Box::new(ReturnStatement::new(og_token, value));
+        }
+
+        // `return a, b` hands back the values together as an array
+        let mut values = vec![value];
+        while self.peek_token_is(&TokenType::COMMA) {
+            self.next_token();
+            self.next_token();
+            values.push(self.parse_expression(PrecedenceType::LOWEST));
+        }
+        let array_token = Token::new(TokenType::LBRACKET, Some("["));
         return Box::new(ReturnStatement::new(
             og_token,
-            self.parse_expression(PrecedenceType::LOWEST),
+            Box::new(ArrayLiteralExpression::new(array_token, values)),
         ));
     }
 
+    fn parse_import_statement(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        if !self.expect_peek(&TokenType::STRING) {
+            panic!("IMPORT EXPECTS A PATH STRING");
+        }
+        let path = self.current_token.literal.clone().unwrap();
+        return Box::new(ImportStatement::new(og_token, path));
+    }
+
     fn parse_expression_statement(&mut self) -> Box<dyn ProgramNode> {
         let expr = self.parse_expression(PrecedenceType::LOWEST);
         return Box::new(ExpressionStatement::new(self.current_token.clone(), expr));
@@ -193,10 +349,18 @@ impl Parser {
 
     fn parse_block_statement(&mut self) -> Box<dyn ProgramNode> {
         let og_token = self.current_token.clone();
-        let mut statements = vec![];
 
         self.next_token();
+        let statements = self.parse_block_rest(vec![]);
 
+        return Box::new(BlockStatement::new(og_token, statements));
+    }
+
+    // Parses statements from the current token up to the closing brace.
+    fn parse_block_rest(
+        &mut self,
+        mut statements: Vec<Box<dyn ProgramNode>>,
+    ) -> Vec<Box<dyn ProgramNode>> {
         while !self.current_token_is(&TokenType::RBRACE) && !self.current_token_is(&TokenType::EOF)
         {
             if !self.current_token_is(&TokenType::SEMICOLON) {
@@ -205,12 +369,41 @@ impl Parser {
             }
             self.next_token();
         }
+        return statements;
+    }
 
-        return Box::new(BlockStatement::new(og_token, statements));
+    // `{` starts either a hash or a block expression. A hash is empty or has
+    // a colon after its first expression, anything else is a block.
+    fn parse_brace_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        if self.peek_token_is(&TokenType::RBRACE) {
+            return self.parse_hash_expression(og_token, None);
+        }
+
+        self.next_token();
+        let first: Box<dyn ProgramNode> = if self.current_token_is(&TokenType::LET)
+            || self.current_token_is(&TokenType::CONST)
+            || self.current_token_is(&TokenType::RETURN)
+        {
+            self.parse_statement()
+        } else {
+            let expr = self.parse_expression(PrecedenceType::LOWEST);
+            if self.peek_token_is(&TokenType::COLON) {
+                return self.parse_hash_expression(og_token, Some(expr));
+            }
+            Box::new(ExpressionStatement::new(self.current_token.clone(), expr))
+        };
+
+        self.next_token();
+        let statements = self.parse_block_rest(vec![first]);
+        let block = BlockStatement::new(og_token.clone(), statements);
+        return Box::new(BlockExpression::new(og_token, Box::new(block)));
     }
 
     fn parse_expression(&mut self, precedence: PrecedenceType) -> Box<dyn ProgramNode> {
         let token_type = self.current_token.token_type;
+        let token = self.current_token.clone();
+        self.record(|| format!("prefix {} at {:?}", describe_token(&token), precedence));
 
         // Parse Left Side of Expression
         let left_expr = match token_type {
@@ -223,17 +416,36 @@ impl Parser {
             TokenType::FALSE => Some(self.parse_boolean_expression()),
             TokenType::LPAREN => Some(self.parse_grouped_expression()),
             TokenType::IF => Some(self.parse_if_expression()),
+            TokenType::WHILE => Some(self.parse_while_expression()),
+            TokenType::LET => Some(self.parse_let_in_expression()),
+            TokenType::DO => Some(self.parse_do_while_expression()),
+            TokenType::TRY => Some(self.parse_try_expression()),
+            TokenType::THROW => Some(self.parse_throw_expression()),
+            TokenType::MATCH => Some(self.parse_match_expression()),
             TokenType::STRING => Some(self.parse_string_expression()),
+            TokenType::TEMPLATE => Some(self.parse_template_expression()),
+            TokenType::LBRACE => Some(self.parse_brace_expression()),
+            TokenType::LBRACKET => Some(self.parse_array_expression()),
 
             _ => None,
         };
 
         if left_expr.is_none() {
-            panic!("LEFT EXPR IS NONE!");
+            panic!("unexpected {}", describe_token(&self.current_token));
         } else {
             let mut expr = left_expr.unwrap();
             while !self.peek_token_is(&TokenType::SEMICOLON) && precedence < self.peek_precedence()
             {
+                let token = self.peek_token.clone();
+                let peek_precedence = self.peek_precedence();
+                self.record(|| {
+                    format!(
+                        "infix {}: {:?} > {:?}, so it takes the left side",
+                        describe_token(&token),
+                        peek_precedence,
+                        precedence
+                    )
+                });
                 self.next_token();
                 let next_token = self.current_token.clone().token_type;
                 expr = match next_token {
@@ -242,14 +454,32 @@ impl Parser {
                     TokenType::MINUS => self.parse_infix_expression(expr),
                     TokenType::SLASH => self.parse_infix_expression(expr),
                     TokenType::ASTERISK => self.parse_infix_expression(expr),
+                    TokenType::POWER => self.parse_infix_expression(expr),
                     TokenType::EQ => self.parse_infix_expression(expr),
+                    TokenType::AND => self.parse_infix_expression(expr),
+                    TokenType::OR => self.parse_infix_expression(expr),
+                    TokenType::COALESCE => self.parse_infix_expression(expr),
                     TokenType::NEQ => self.parse_infix_expression(expr),
                     TokenType::GT => self.parse_infix_expression(expr),
                     TokenType::LT => self.parse_infix_expression(expr),
+                    TokenType::LTE => self.parse_infix_expression(expr),
+                    TokenType::GTE => self.parse_infix_expression(expr),
                     TokenType::LPAREN => self.parse_call_expression(expr),
+                    TokenType::PIPE => self.parse_pipe_expression(expr),
+                    TokenType::LBRACKET => self.parse_index_expression(expr),
                     _ => panic!("PANICKING!"),
                 };
             }
+            let token = self.peek_token.clone();
+            let peek_precedence = self.peek_precedence();
+            self.record(|| {
+                format!(
+                    "stop before {}: {:?} <= {:?}",
+                    describe_token(&token),
+                    peek_precedence,
+                    precedence
+                )
+            });
             return expr;
         }
     }
@@ -261,15 +491,44 @@ impl Parser {
         ));
     }
 
+    // Each `${...}` is parsed on its own, and must hold a single expression.
+    fn parse_template_expression(&mut self) -> Box<dyn ProgramNode> {
+        let token = self.current_token.clone();
+        let mut parts: Vec<TemplatePart> = vec![];
+        for chunk in split_template(token.literal.as_ref().unwrap()) {
+            match chunk {
+                TemplateChunk::Text(text) => parts.push(TemplatePart::Text(text)),
+                TemplateChunk::Source(source) => {
+                    let mut parser = Parser::new(Lexer::new(source));
+                    let statements = parser.parse();
+                    if statements.len() == 0 {
+                        panic!("EMPTY TEMPLATE EXPRESSION");
+                    }
+                    if statements.len() != 1 {
+                        panic!("TEMPLATE EXPRESSION MUST BE A SINGLE EXPRESSION");
+                    }
+                    let statement = statements[0].downcast_ref::<ExpressionStatement>();
+                    if statement.is_none() {
+                        panic!("TEMPLATE EXPRESSION MUST BE A SINGLE EXPRESSION");
+                    }
+                    parts.push(TemplatePart::Expression(
+                        statement.unwrap().expression.get_copy(),
+                    ));
+                }
+            }
+        }
+        return Box::new(TemplateExpression::new(token, parts));
+    }
+
     fn parse_integer_expression(&mut self) -> Box<dyn ProgramNode> {
+        let literal = self.current_token.clone().literal.unwrap();
+        let value = literal.parse::<i64>();
+        if value.is_err() {
+            panic!("integer literal out of range: {}", literal);
+        }
         return Box::new(IntegerLiteralExpression::new(
             self.current_token.clone(),
-            self.current_token
-                .clone()
-                .literal
-                .unwrap()
-                .parse::<i64>()
-                .unwrap(),
+            value.unwrap(),
         ));
     }
 
@@ -296,6 +555,23 @@ impl Parser {
         let og_token = self.current_token.clone();
         self.next_token();
 
+        // i64::MIN only fits once its minus sign is part of the literal.
+        // Left alone under `**`, which binds tighter than the minus
+        if og_token.token_type == TokenType::MINUS
+            && self.current_token_is(&TokenType::INT)
+            && !self.peek_token_is(&TokenType::POWER)
+        {
+            let literal = format!("-{}", self.current_token.literal.clone().unwrap());
+            if literal == i64::MIN.to_string() {
+                let token = Token {
+                    token_type: TokenType::INT,
+                    literal: Some(literal),
+                    span: (og_token.span.0, self.current_token.span.1),
+                };
+                return Box::new(IntegerLiteralExpression::new(token, i64::MIN));
+            }
+        }
+
         return Box::new(PrefixExpression::new(
             og_token.clone(),
             og_token.literal.clone().unwrap(),
@@ -305,7 +581,11 @@ impl Parser {
     fn parse_infix_expression(&mut self, left: Box<dyn ProgramNode>) -> Box<dyn ProgramNode> {
         let og_token = self.current_token.clone();
 
-        let precedence = PRECEDENCE_MAP[&og_token.token_type];
+        let mut precedence = PRECEDENCE_MAP[&og_token.token_type];
+        // `**` is right associative, so the right side may hold another `**`
+        if precedence == PrecedenceType::POWER {
+            precedence = PrecedenceType::PREFIX;
+        }
         self.next_token();
         return Box::new(InfixExpression::new(
             og_token.clone(),
@@ -316,6 +596,7 @@ impl Parser {
     }
 
     fn parse_grouped_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
         self.next_token();
 
         let expr = self.parse_expression(PrecedenceType::LOWEST);
@@ -323,11 +604,16 @@ impl Parser {
             panic!("{}", "DOES NOT INCLUDE RPAREN");
         }
 
-        return expr;
+        return Box::new(GroupedExpression::new(og_token, expr));
     }
 
     fn parse_if_expression(&mut self) -> Box<dyn ProgramNode> {
         let og_token = self.current_token.clone();
+        if !self.peek_token_is(&TokenType::LPAREN) {
+            self.next_token();
+            let condition = self.parse_expression(PrecedenceType::LOWEST);
+            return self.parse_then_else(og_token, condition);
+        }
         if !self.expect_peek(&TokenType::LPAREN) {
             panic!("INVALID!");
         }
@@ -340,6 +626,10 @@ impl Parser {
             panic!("INVALID 2");
         }
 
+        if self.peek_token_is(&TokenType::THEN) {
+            return self.parse_then_else(og_token, condition);
+        }
+
         if !self.expect_peek(&TokenType::LBRACE) {
             panic!("INVALID 3");
         }
@@ -349,10 +639,16 @@ impl Parser {
         let alternative: Option<Box<dyn ProgramNode>>;
         if self.peek_token_is(&TokenType::ELSE) {
             self.next_token();
-            if !self.expect_peek(&TokenType::LBRACE) {
-                panic!("INVALID!!!!");
+            // `else if` chains nest the next if as the alternative
+            if self.peek_token_is(&TokenType::IF) {
+                self.next_token();
+                alternative = Some(self.parse_if_expression());
+            } else {
+                if !self.expect_peek(&TokenType::LBRACE) {
+                    panic!("INVALID!!!!");
+                }
+                alternative = Some(self.parse_block_statement());
             }
-            alternative = Some(self.parse_block_statement());
         } else {
             alternative = None;
         }
@@ -365,6 +661,167 @@ impl Parser {
         ));
     }
 
+    // `if cond then a else b`, with plain expressions for the branches.
+    fn parse_then_else(
+        &mut self,
+        og_token: Token,
+        condition: Box<dyn ProgramNode>,
+    ) -> Box<dyn ProgramNode> {
+        if !self.expect_peek(&TokenType::THEN) {
+            panic!("IF WITHOUT PARENTHESES MUST USE THEN");
+        }
+        self.next_token();
+        let consequence = self.parse_expression(PrecedenceType::LOWEST);
+
+        let mut alternative: Option<Box<dyn ProgramNode>> = None;
+        if self.peek_token_is(&TokenType::ELSE) {
+            self.next_token();
+            self.next_token();
+            alternative = Some(self.parse_expression(PrecedenceType::LOWEST));
+        }
+
+        return Box::new(IfExpression::new(
+            og_token,
+            condition,
+            consequence,
+            alternative,
+        ));
+    }
+
+    // Parses `(condition)`, leaving the current token on the closing paren.
+    fn parse_loop_condition(&mut self) -> Box<dyn ProgramNode> {
+        if !self.expect_peek(&TokenType::LPAREN) {
+            panic!("INVALID LOOP CONDITION");
+        }
+        self.next_token();
+
+        let condition = self.parse_expression(PrecedenceType::LOWEST);
+        if !self.expect_peek(&TokenType::RPAREN) {
+            panic!("INVALID LOOP CONDITION");
+        }
+        return condition;
+    }
+
+    fn parse_while_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        let condition = self.parse_loop_condition();
+
+        if !self.expect_peek(&TokenType::LBRACE) {
+            panic!("INVALID WHILE BODY");
+        }
+        let body = self.parse_block_statement();
+
+        return Box::new(WhileExpression::new(og_token, condition, body, true));
+    }
+
+    fn parse_do_while_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        if !self.expect_peek(&TokenType::LBRACE) {
+            panic!("INVALID DO BODY");
+        }
+        let body = self.parse_block_statement();
+
+        if !self.expect_peek(&TokenType::WHILE) {
+            panic!("DO BODY MUST BE FOLLOWED BY WHILE");
+        }
+        let condition = self.parse_loop_condition();
+
+        return Box::new(WhileExpression::new(og_token, condition, body, false));
+    }
+
+    fn parse_try_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        if !self.expect_peek(&TokenType::LBRACE) {
+            panic!("INVALID TRY BODY");
+        }
+        let body = self.parse_block_statement();
+
+        if !self.expect_peek(&TokenType::CATCH) {
+            panic!("TRY BODY MUST BE FOLLOWED BY CATCH");
+        }
+        if !self.expect_peek(&TokenType::LPAREN) || !self.expect_peek(&TokenType::IDENT) {
+            panic!("CATCH MUST NAME THE ERROR, e.g. catch (e)");
+        }
+        let name = self.parse_identifier_expression();
+        if !self.expect_peek(&TokenType::RPAREN) {
+            panic!("CATCH MUST NAME THE ERROR, e.g. catch (e)");
+        }
+
+        if !self.expect_peek(&TokenType::LBRACE) {
+            panic!("INVALID CATCH BODY");
+        }
+        let handler = self.parse_block_statement();
+
+        return Box::new(TryExpression::new(og_token, body, name, handler));
+    }
+
+    fn parse_throw_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        self.next_token();
+        let value = self.parse_expression(PrecedenceType::LOWEST);
+        return Box::new(ThrowExpression::new(og_token, value));
+    }
+
+    fn parse_match_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        if !self.expect_peek(&TokenType::LPAREN) {
+            panic!("INVALID MATCH SUBJECT");
+        }
+        self.next_token();
+        let subject = self.parse_expression(PrecedenceType::LOWEST);
+        if !self.expect_peek(&TokenType::RPAREN) || !self.expect_peek(&TokenType::LBRACE) {
+            panic!("INVALID MATCH SUBJECT");
+        }
+
+        let mut arms = vec![];
+        while !self.peek_token_is(&TokenType::RBRACE) {
+            self.next_token();
+            let pattern = self.parse_match_pattern();
+            if !self.expect_peek(&TokenType::ARROW) {
+                panic!("MATCH PATTERN MUST BE FOLLOWED BY =>");
+            }
+            self.next_token();
+            let value = self.parse_expression(PrecedenceType::LOWEST);
+            arms.push(MatchArm { pattern, value });
+
+            if !self.peek_token_is(&TokenType::RBRACE) && !self.expect_peek(&TokenType::COMMA) {
+                panic!("INVALID MATCH ARM");
+            }
+        }
+        self.next_token();
+
+        return Box::new(MatchExpression::new(og_token, subject, arms));
+    }
+
+    // A pattern is `_`, a literal, or an inclusive integer range `lo..hi`.
+    fn parse_match_pattern(&mut self) -> MatchPattern {
+        if self.current_token_is(&TokenType::IDENT)
+            && self.current_token.literal.as_deref() == Some("_")
+        {
+            return MatchPattern::Wildcard;
+        }
+
+        let literal_start = [
+            TokenType::INT,
+            TokenType::MINUS,
+            TokenType::STRING,
+            TokenType::TRUE,
+            TokenType::FALSE,
+        ];
+        if !literal_start.contains(&self.current_token.token_type) {
+            panic!("INVALID MATCH PATTERN");
+        }
+        let value = self.parse_expression(PrecedenceType::PREFIX);
+
+        if self.peek_token_is(&TokenType::DOTDOT) {
+            self.next_token();
+            self.next_token();
+            let high = self.parse_expression(PrecedenceType::PREFIX);
+            return MatchPattern::Range(value, high);
+        }
+        return MatchPattern::Value(value);
+    }
+
     fn parse_function_expression(&mut self) -> Box<dyn ProgramNode> {
         let og_token = self.current_token.clone();
 
@@ -391,21 +848,19 @@ impl Parser {
         }
 
         self.next_token();
-
-        let ident = IdentifierExpression {
-            token: self.current_token.clone(),
-            value: self.current_token.literal.clone().unwrap(),
-        };
-
-        identifiers.push(Box::new(ident));
+        identifiers.push(self.parse_function_parameter());
 
         while self.peek_token_is(&TokenType::COMMA) {
+            if identifiers[identifiers.len() - 1]
+                .downcast_ref::<RestParameterExpression>()
+                .is_some()
+            {
+                panic!("Rest parameter must be the last parameter");
+            }
+
             self.next_token();
             self.next_token();
-            identifiers.push(Box::new(IdentifierExpression {
-                token: self.current_token.clone(),
-                value: self.current_token.literal.clone().unwrap(),
-            }));
+            identifiers.push(self.parse_function_parameter());
         }
 
         if !self.expect_peek(&TokenType::RPAREN) {
@@ -415,6 +870,25 @@ impl Parser {
         return identifiers;
     }
 
+    fn parse_function_parameter(&mut self) -> Box<dyn ProgramNode> {
+        if self.current_token_is(&TokenType::ELLIPSIS) {
+            let og_token = self.current_token.clone();
+            if !self.expect_peek(&TokenType::IDENT) {
+                panic!("INVALID Rest Parameter");
+            }
+
+            return Box::new(RestParameterExpression::new(
+                og_token,
+                self.current_token.literal.clone().unwrap(),
+            ));
+        }
+
+        return Box::new(IdentifierExpression {
+            token: self.current_token.clone(),
+            value: self.current_token.literal.clone().unwrap(),
+        });
+    }
+
     fn parse_call_expression(&mut self, func: Box<dyn ProgramNode>) -> Box<dyn ProgramNode> {
         let og_token = self.current_token.clone();
         let arguments = self.parse_call_arguments();
@@ -422,10 +896,74 @@ impl Parser {
         return Box::new(CallExpression::new(og_token, func, arguments));
     }
 
+    fn parse_pipe_expression(&mut self, left: Box<dyn ProgramNode>) -> Box<dyn ProgramNode> {
+        // `x |> f` is sugar for `f(x)`, so it is desugared into a call here
+        let og_token = self.current_token.clone();
+        self.next_token();
+
+        let func = self.parse_expression(PrecedenceType::PIPE);
+        return Box::new(CallExpression::new(og_token, func, vec![left]));
+    }
+
+    fn parse_index_expression(&mut self, left: Box<dyn ProgramNode>) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        self.next_token();
+
+        let index = self.parse_expression(PrecedenceType::LOWEST);
+        if !self.expect_peek(&TokenType::RBRACKET) {
+            panic!("INVALID INDEX EXPRESSION");
+        }
+
+        return Box::new(IndexExpression::new(og_token, left, index));
+    }
+
+    // `first_key` is the already parsed first key, if any.
+    fn parse_hash_expression(
+        &mut self,
+        og_token: Token,
+        first_key: Option<Box<dyn ProgramNode>>,
+    ) -> Box<dyn ProgramNode> {
+        let mut pairs = vec![];
+        let mut key = first_key;
+
+        loop {
+            if key.is_none() {
+                if self.peek_token_is(&TokenType::RBRACE) {
+                    break;
+                }
+                self.next_token();
+                key = Some(self.parse_expression(PrecedenceType::LOWEST));
+            }
+
+            if !self.expect_peek(&TokenType::COLON) {
+                panic!("INVALID HASH KEY");
+            }
+
+            self.next_token();
+            let value = self.parse_expression(PrecedenceType::LOWEST);
+            pairs.push((key.take().unwrap(), value));
+
+            if !self.peek_token_is(&TokenType::RBRACE) && !self.expect_peek(&TokenType::COMMA) {
+                panic!("INVALID HASH LITERAL");
+            }
+        }
+
+        if !self.expect_peek(&TokenType::RBRACE) {
+            panic!("INVALID HASH LITERAL");
+        }
+
+        return Box::new(HashLiteralExpression::new(og_token, pairs));
+    }
+
     fn parse_call_arguments(&mut self) -> Vec<Box<dyn ProgramNode>> {
+        return self.parse_expression_list(&TokenType::RPAREN, "INVALID CALL ARGUMENT");
+    }
+
+    // Parses comma separated expressions up to and including `end`.
+    fn parse_expression_list(&mut self, end: &TokenType, error: &str) -> Vec<Box<dyn ProgramNode>> {
         let mut args = vec![];
 
-        if self.peek_token_is(&TokenType::RPAREN) {
+        if self.peek_token_is(end) {
             self.next_token();
             return args;
         }
@@ -439,21 +977,30 @@ impl Parser {
             args.push(self.parse_expression(PrecedenceType::LOWEST));
         }
 
-        if !self.expect_peek(&TokenType::RPAREN) {
-            panic!("INVALID CALL ARGUMENT");
+        if !self.expect_peek(end) {
+            panic!("{}", error);
         }
 
         return args;
     }
+
+    fn parse_array_expression(&mut self) -> Box<dyn ProgramNode> {
+        let og_token = self.current_token.clone();
+        let elements = self.parse_expression_list(&TokenType::RBRACKET, "INVALID ARRAY LITERAL");
+        return Box::new(ArrayLiteralExpression::new(og_token, elements));
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::environment::Environment;
     use crate::program::Program;
-    use crate::types::{Boolean, Integer, Type};
+    use crate::types::{Array, Boolean, Error, ErrorKind, Integer, Type};
 
     use super::*;
+    use crate::builtins::set_input;
     use crate::types::StringLiteral;
+    use std::io::Cursor;
 
     #[test]
     fn test_let_statements() {
@@ -471,13 +1018,13 @@ mod tests {
             "Statements Returned Does not Equal 3"
         );
 
-        let test_literals = vec!["x", "y", "foobar"];
-        for i in 0..test_literals.len() {
+        let test_literals = ["x", "y", "foobar"];
+        for (i, literal) in test_literals.iter().enumerate() {
             test_let_statement(
                 program.statements[i]
                     .downcast_ref::<LetStatement>()
                     .unwrap(),
-                test_literals[i].to_string(),
+                literal.to_string(),
             );
         }
 
@@ -627,6 +1174,18 @@ mod tests {
         let test_inputs = vec![
             ("-a + b;", "((-a) + b)"),
             ("!-a;", "(!(-a))"),
+            ("-add(5);", "(-add(5))"),
+            ("5 > 3 && 2 < 4;", "((5 > 3) && (2 < 4))"),
+            ("a || b && c == d;", "(a || (b && (c == d)))"),
+            ("a && b || c;", "((a && b) || c)"),
+            ("true && false || true;", "((true && false) || true)"),
+            ("true || false && false;", "(true || (false && false))"),
+            ("a ?? b || c;", "(a ?? (b || c))"),
+            ("a ?? b ?? c;", "((a ?? b) ?? c)"),
+            ("!f(a)(b);", "(!f(a)(b))"),
+            ("-a[0] * b;", "((-(a[0])) * b)"),
+            ("[-1, -2];", "[(-1), (-2)]"),
+            ("f(-1, a - -b);", "f((-1), (a - (-b)))"),
             ("a + b + c;", "((a + b) + c)"),
             ("a + b - c;", "((a + b) - c)"),
             ("a * b * c;", "((a * b) * c)"),
@@ -693,6 +1252,14 @@ mod tests {
             ("2 / (5 + 5)", "(2 / (5 + 5))"),
             ("-(5 + 5)", "(-(5 + 5))"),
             ("!(true == true)", "(!(true == true))"),
+            ("(5)", "(5)"),
+            ("((5))", "((5))"),
+            ("((1 + 2))", "((1 + 2))"),
+            ("(x)(1)", "(x)(1)"),
+            ("a * b[2] + 1", "((a * (b[2])) + 1)"),
+            ("f(x)[0]", "(f(x)[0])"),
+            ("[1, 2 * 3][0][1]", "(([1, (2 * 3)][0])[1])"),
+            ("f()[0](5)", "(f()[0])(5)"),
         ];
 
         for test_input in test_inputs {
@@ -864,37 +1431,188 @@ mod tests {
     }
 
     #[test]
-    fn test_call_expression() {
+    fn test_rest_parameter_literal() {
         let test_inputs = vec![
-            ("add(1, 2, 3)", "add(1, 2, 3)", 1, 3),
+            ("fn(...nums) { nums; }", "fn(...nums) { nums; }", 1),
             (
-                "product(1,2*3, 4+5); run()",
-                "product(1, (2 * 3), (4 + 5))",
+                "fn(first, ...rest) { rest; }",
+                "fn(first, ...rest) { rest; }",
                 2,
-                3,
             ),
-            ("run()", "run()", 1, 0),
         ];
         for test_input in test_inputs {
             let lexer = Lexer::new(test_input.0.to_string());
             let mut parser = Parser::new(lexer);
             let program = Program::new(parser.parse());
-            assert_eq!(program.statements.len(), test_input.2);
             assert_eq!(program.statements[0].to_string(), test_input.1);
-            assert_eq!(
-                program.statements[0]
-                    .downcast_ref::<ExpressionStatement>()
-                    .unwrap()
-                    .expression
-                    .downcast_ref::<CallExpression>()
-                    .unwrap()
-                    .arguments
-                    .len(),
+
+            let parameters = &program.statements[0]
+                .downcast_ref::<ExpressionStatement>()
+                .unwrap()
+                .expression
+                .downcast_ref::<FunctionLiteralExpression>()
+                .unwrap()
+                .parameters;
+            assert_eq!(parameters.len(), test_input.2);
+            assert!(parameters[parameters.len() - 1]
+                .downcast_ref::<RestParameterExpression>()
+                .is_some());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Rest parameter must be the last parameter")]
+    fn test_rest_parameter_not_last() {
+        let lexer = Lexer::new("fn(...rest, last) { last; }".to_string());
+        let mut parser = Parser::new(lexer);
+        parser.parse();
+    }
+
+    #[test]
+    fn test_eval_rest_parameters() {
+        let test_inputs = vec![
+            ("let sum = fn(...nums) { nums }; sum()", 0, "[]"),
+            ("let sum = fn(...nums) { nums }; sum(5)", 5, "[5]"),
+            (
+                "let sum = fn(...nums) { nums }; sum(1, 2, 3, 4)",
+                10,
+                "[1, 2, 3, 4]",
+            ),
+            (
+                "let sum = fn(first, ...rest) { rest }; sum(1, 2, 3)",
+                5,
+                "[2, 3]",
+            ),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            let obj = program.eval().unwrap();
+            assert_eq!(obj.inspect(), test_input.2);
+
+            let total: i64 = obj
+                .downcast_ref::<Array>()
+                .unwrap()
+                .elements
+                .iter()
+                .map(|x| x.downcast_ref::<Integer>().unwrap().value)
+                .sum();
+            assert_eq!(total, test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_call_expression() {
+        let test_inputs = vec![
+            ("add(1, 2, 3)", "add(1, 2, 3)", 1, 3),
+            (
+                "product(1,2*3, 4+5); run()",
+                "product(1, (2 * 3), (4 + 5))",
+                2,
+                3,
+            ),
+            ("run()", "run()", 1, 0),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let program = Program::new(parser.parse());
+            assert_eq!(program.statements.len(), test_input.2);
+            assert_eq!(program.statements[0].to_string(), test_input.1);
+            assert_eq!(
+                program.statements[0]
+                    .downcast_ref::<ExpressionStatement>()
+                    .unwrap()
+                    .expression
+                    .downcast_ref::<CallExpression>()
+                    .unwrap()
+                    .arguments
+                    .len(),
                 test_input.3
             );
         }
     }
 
+    #[test]
+    fn test_empty_collection_literals() {
+        let lexer = Lexer::new("[]; {}; [ ]; { }".to_string());
+        let mut parser = Parser::new(lexer);
+        let program = Program::new(parser.parse());
+        assert_eq!(program.statements.len(), 4);
+        for (i, statement) in program.statements.iter().enumerate() {
+            let expression = &statement
+                .downcast_ref::<ExpressionStatement>()
+                .unwrap()
+                .expression;
+            if i % 2 == 0 {
+                let array = expression.downcast_ref::<ArrayLiteralExpression>();
+                assert_eq!(array.unwrap().elements.len(), 0);
+            } else {
+                let hash = expression.downcast_ref::<HashLiteralExpression>();
+                assert_eq!(hash.unwrap().pairs.len(), 0);
+            }
+        }
+
+        let test_inputs = vec![("[]", "[]"), ("{}", "{}"), ("[[], {}]", "[[], {}]")];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_parse_with_trace() {
+        let lexer = Lexer::new("2 + 3 * 4".to_string());
+        let mut parser = Parser::new(lexer);
+        let (statements, trace) = parser.parse_with_trace();
+        assert_eq!(statements[0].to_string(), "(2 + (3 * 4))");
+        assert_eq!(
+            trace,
+            vec![
+                "prefix `2` at LOWEST",
+                "infix `+`: SUM > LOWEST, so it takes the left side",
+                "prefix `3` at SUM",
+                "infix `*`: PRODUCT > SUM, so it takes the left side",
+                "prefix `4` at PRODUCT",
+                "stop before EOF: LOWEST <= PRODUCT",
+                "stop before EOF: LOWEST <= SUM",
+                "stop before EOF: LOWEST <= LOWEST",
+            ]
+        );
+
+        let lexer = Lexer::new("2 * 3 + 4".to_string());
+        let mut parser = Parser::new(lexer);
+        let (statements, trace) = parser.parse_with_trace();
+        assert_eq!(statements[0].to_string(), "((2 * 3) + 4)");
+        assert!(trace.contains(&"stop before `+`: SUM <= PRODUCT".to_string()));
+
+        // Plain parsing doesn't keep a trace
+        let lexer = Lexer::new("1 + 2".to_string());
+        let mut parser = Parser::new(lexer);
+        parser.parse();
+        assert!(parser.trace.is_none());
+    }
+
+    #[test]
+    fn test_pipe_expression() {
+        let test_inputs = vec![
+            ("x |> f", "f(x)"),
+            ("x |> f |> g", "g(f(x))"),
+            ("1 + 2 |> f", "f((1 + 2))"),
+        ];
+
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let program = Program::new(parser.parse());
+            assert_eq!(program.statements.len(), 1);
+            assert_eq!(program.statements[0].to_string(), test_input.1);
+        }
+    }
+
     #[test]
     fn test_eval_integer_expression() {
         let test_inputs = vec![
@@ -902,6 +1620,9 @@ mod tests {
             ("10", 10),
             ("-5", -5),
             ("-10", -10),
+            ("0xFF_FF", 65535),
+            ("-0b1010_1010", -170),
+            ("0x10 + 0b1_0000", 32),
             ("5 + 5", 10),
             ("5 - 5", 0),
             ("5 * 5", 25),
@@ -921,6 +1642,10 @@ mod tests {
             ("return 15; 19 + 15; 5 == 5;", 15),
             ("10 == 10; 10 != 11; return 1;", 1),
             ("let a = 10; a;", 10),
+            ("let x1 = 3; let foo_bar = 4; x1 * foo_bar", 12),
+            ("let _ = 7; let _tmp = _ + 1; _tmp", 8),
+            ("let double = fn(x) { x * 2 }; 5 |> double", 10),
+            ("let double = fn(x) { x * 2 }; 5 |> double |> double", 20),
         ];
         for test_input in test_inputs {
             test_eval_integer(test_input);
@@ -962,81 +1687,1074 @@ mod tests {
             ("(2 == 2) == true", true),
             ("(2 < 1) == false", true),
             ("let b = true; b;", true),
+            ("!5", false),
+            ("!!5", true),
+            ("let f = fn(x) { !x }; f(if (false) { 1 })", true),
         ];
         for test_input in test_inputs {
             test_eval_boolean(test_input);
         }
     }
 
-    fn test_eval_boolean(test_input: (&str, bool)) {
-        let lexer = Lexer::new(test_input.0.to_string());
+    #[test]
+    fn test_semicolon_separated_statements() {
+        let test_inputs = vec!["1+1; 2+2; 3+3", "1+1;2+2;3+3;", "1+1; ;; 2+2;\n3+3"];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.to_string());
+            let mut parser = Parser::new(lexer);
+            let statements = parser.parse();
+            assert_eq!(statements.len(), 3);
+
+            let mut env = Environment::new();
+            let expected = vec!["2", "4", "6"];
+            for (statement, value) in statements.iter().zip(expected) {
+                assert!(statement.downcast_ref::<ExpressionStatement>().is_some());
+                assert_eq!(statement.eval(&mut env).unwrap().inspect(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_escaped_identifiers() {
+        let test_inputs = vec![
+            ("let `if` = 5; `if` * 2", "10"),
+            ("let f = fn(`fn`) { `fn` + 1 }; f(1)", "2"),
+            ("let `let` = 1 in `let`", "1"),
+            ("let `x` = 3; x", "3"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("let `while` = `x` + 1".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.parse()[0].to_string(), "let `while` = (x + 1)");
+    }
+
+    #[test]
+    fn test_eval_block_expression() {
+        let test_inputs = vec![
+            ("let y = { let a = 1; a + 2 }; y", "3"),
+            ("let y = { let a = 1; a + 2 }; a", "unknown identifier: a"),
+            ("let a = 10; let y = { let a = 1; a }; a + y", "11"),
+            ("let x = 4; { x * 2 }", "8"),
+            ("{ let a = 1; }", "null"),
+            ("let h = {}; len(keys(h))", "0"),
+            ("let x = \"k\"; { x: 1 }[\"k\"]", "1"),
+            ("{ 1; 2; 3 } + 1", "4"),
+            ("let f = fn() { let v = { return 5; 6 }; v + 1 }; f()", "6"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("let y = { let a = 1; a + 2 };".to_string());
         let mut parser = Parser::new(lexer);
-        let mut program = Program::new(parser.parse());
-        let obj = program.eval();
-        assert!(obj.is_some());
-        let unwrapped = obj.unwrap();
-        assert_eq!(&unwrapped.type_(), &Type::BOOLEAN);
         assert_eq!(
-            &unwrapped.downcast_ref::<Boolean>().unwrap().value,
-            &test_input.1
+            parser.parse()[0].to_string(),
+            "let y = { let a = 1; (a + 2); }"
         );
     }
 
     #[test]
-    fn test_if_expression_integer() {
+    fn test_eval_try() {
         let test_inputs = vec![
-            ("if (5 == 5) { 10; }", 10),
-            ("if (1 == 2) { 10; } else { 5; }", 5),
+            ("try { 1 + 2 } catch (e) { 0 }", "3"),
+            ("try { 1 / 0 } catch (e) { e }", "\"division by zero\""),
+            ("try { missing } catch (e) { len(e) > 0 }", "true"),
+            ("let x = try { 1 + true } catch (e) { -1 }; x * 2", "-2"),
+            ("try { let a = 1; } catch (e) { 0 }", "null"),
+            (
+                "try { try { 1 / 0 } catch (e) { e + \"!\" } } catch (e) { 0 }",
+                "\"division by zero!\"",
+            ),
+            // The caught name only exists inside the handler
+            ("try { 1 / 0 } catch (e) { 0 }; e", "unknown identifier: e"),
+            // Errors inside the handler propagate
+            (
+                "try { 1 / 0 } catch (e) { e * 2 }",
+                "type mismatch: STRING * INTEGER",
+            ),
+            (
+                "let f = fn(n) { try { 10 / n } catch (e) { 0 } }; [f(2), f(0)]",
+                "[5, 0]",
+            ),
         ];
         for test_input in test_inputs {
-            test_eval_integer(test_input);
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
         }
+
+        let lexer = Lexer::new("try { f(x) } catch (err) { puts(err); 0 }".to_string());
+        let mut parser = Parser::new(lexer);
+        let statements = parser.parse();
+        assert_eq!(
+            statements[0].to_string(),
+            "try { f(x); } catch (err) { puts(err); 0; }"
+        );
+
+        // Children come in source order
+        let children: Vec<String> = statements[0].children()[0]
+            .children()
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(children, vec!["f(x);", "err", "puts(err); 0;"]);
     }
 
     #[test]
-    fn test_error_handling() {
+    fn test_eval_throw() {
         let test_inputs = vec![
-            ("5 + true", "type mismatch: INTEGER + BOOLEAN"),
-            ("true - 10", "type mismatch: BOOLEAN - INTEGER"),
+            ("try { throw \"boom\" } catch (e) { e }", "\"boom\""),
+            ("try { throw 1 + 2; 4 } catch (e) { e }", "3"),
+            ("try { throw [1, \"a\"] } catch (e) { e }", "[1, \"a\"]"),
             (
-                "10; 5 + true; return 15;",
-                "type mismatch: INTEGER + BOOLEAN",
+                "try { throw {\"code\": 404, \"path\": \"/x\"} } catch (e) { [e[\"code\"], e[\"path\"]] }",
+                "[404, \"/x\"]",
+            ),
+            (
+                "let f = fn() { throw {\"code\": 1} }; try { f() } catch (e) { e[\"code\"] + 1 }",
+                "2",
+            ),
+            (
+                "let check = fn(n) { if (n < 0) { throw \"negative\" } else { n } }; try { check(-1) } catch (e) { e }",
+                "\"negative\"",
             ),
-            ("-true", "invalid type: -BOOLEAN"),
-            ("-(5 + true)", "type mismatch: INTEGER + BOOLEAN"),
-            ("if (5 + true) { x }", "type mismatch: INTEGER + BOOLEAN"),
-            ("foobar;", "unknown identifier: foobar"),
+            ("try { throw missing } catch (e) { e }", "\"unknown identifier: missing\""),
         ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        test_eval_error(("throw \"boom\"; 1", ErrorKind::Thrown, "boom"));
+        test_eval_error((
+            "throw {\"code\": 404}",
+            ErrorKind::Thrown,
+            "{\"code\": 404}",
+        ));
+        test_eval_error((
+            "let f = fn() { throw \"inner\" }; f() + 1",
+            ErrorKind::Thrown,
+            "inner",
+        ));
+    }
 
+    #[test]
+    fn test_eval_match() {
+        let test_inputs = vec![
+            ("match (2) { 1 => \"one\", 2 => \"two\", _ => \"many\" }", "\"two\""),
+            ("match (7) { 1 => \"one\", 2 => \"two\", _ => \"many\" }", "\"many\""),
+            ("match (1 < 2) { true => 1, false => 0 }", "1"),
+            ("match (\"b\") { \"a\" => 1, \"b\" => 2, }", "2"),
+            ("match (-3) { -3 => \"neg\", _ => \"other\" }", "\"neg\""),
+            // Ranges include both ends
+            ("let grade = fn(n) { match (n) { 90..100 => \"A\", 80..89 => \"B\", _ => \"C\" } }; [grade(100), grade(85), grade(89), grade(12)]", "[\"A\", \"B\", \"B\", \"C\"]"),
+            // Arms are tried in order and only the chosen value is evaluated
+            ("match (1) { _ => 0, 1 => missing }", "0"),
+            ("match (1) { 2 => missing, 1 => { let a = 2; a * 3 } }", "6"),
+            ("match (\"1\") { 1 => \"int\", _ => \"string\" }", "\"string\""),
+            ("match (\"x\") { 1..3 => 1, _ => 0 }", "0"),
+        ];
         for test_input in test_inputs {
-            test_eval_error(test_input);
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
         }
+
+        test_eval_error((
+            "match (5) { 1 => 1, 2..4 => 2 }",
+            ErrorKind::NoMatchingArm,
+            "no match arm for 5",
+        ));
+        test_eval_error((
+            "match (missing) { _ => 1 }",
+            ErrorKind::UndefinedIdentifier,
+            "unknown identifier: missing",
+        ));
+
+        let lexer = Lexer::new("match (x) { 1 => a, -2..4 => b + 1, _ => c }".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(
+            parser.parse()[0].to_string(),
+            "match (x) { 1 => a, (-2)..4 => (b + 1), _ => c }"
+        );
     }
 
-    fn test_eval_error(test_input: (&str, &str)) {
-        let lexer = Lexer::new(test_input.0.to_string());
+    #[test]
+    fn test_eval_const() {
+        let test_inputs = vec![
+            ("const x = 5; x * 2", "10"),
+            ("let x = 1; let x = x + 1; x", "2"),
+            ("let x = 1; const x = 2; x", "2"),
+            ("const x = 5; let f = fn(x) { x + 1 }; f(1)", "2"),
+            ("const x = 5; let y = 1 in y + x", "6"),
+            ("const c = 1; let f = fn() { let c = 5; c }; f() + c", "6"),
+            ("const c = 1; let f = fn() { const c = 5; c }; f() + c", "6"),
+            (
+                "let f = fn() { const c = 5; let g = fn() { let c = 2; c }; g() + c }; f()",
+                "7",
+            ),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        test_eval_error((
+            "const x = 5; let x = 6; x",
+            ErrorKind::ConstantReassigned,
+            "cannot reassign constant: x",
+        ));
+        test_eval_error((
+            "const x = 5; const x = 6; x",
+            ErrorKind::ConstantReassigned,
+            "cannot reassign constant: x",
+        ));
+        test_eval_error((
+            "const x = 5; if (true) { let x = 6; x }",
+            ErrorKind::ConstantReassigned,
+            "cannot reassign constant: x",
+        ));
+
+        let lexer = Lexer::new("const limit = 10;".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.parse()[0].to_string(), "const limit = 10");
+    }
+
+    #[test]
+    fn test_eval_import() {
+        let dir = std::env::temp_dir();
+        let temp_path = |name: &str| {
+            let path = dir.join(format!(
+                "blang_import_{}_{}.blang",
+                name,
+                std::process::id()
+            ));
+            return path.to_str().unwrap().to_string();
+        };
+        let lib = temp_path("lib");
+        let cycle_a = temp_path("cycle_a");
+        let cycle_b = temp_path("cycle_b");
+        let broken = temp_path("broken");
+        std::fs::write(&lib, "let double = fn(x) { x * 2 };\nconst base = 10;").unwrap();
+        std::fs::write(&cycle_a, format!("import \"{}\"; let a = 1;", cycle_b)).unwrap();
+        std::fs::write(&cycle_b, format!("import \"{}\"; let b = 2;", cycle_a)).unwrap();
+        std::fs::write(&broken, "let x = 1;\nlet y = x / 0;").unwrap();
+
+        let run = |input: String| {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            return program.eval().unwrap().inspect();
+        };
+        let imported = run(format!("import \"{}\"; double(base) + 1", lib));
+        let reassigned = run(format!("import \"{}\"; let base = 1; base", lib));
+        let missing = run("import \"/nonexistent/lib.blang\"; 1".to_string());
+        let cycle = run(format!("import \"{}\"; a", cycle_a));
+        let failed = run(format!("import \"{}\"; 1", broken));
+        for path in [&lib, &cycle_a, &cycle_b, &broken] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(imported, "21");
+        assert_eq!(reassigned, "cannot reassign constant: base");
+        assert!(missing.starts_with("cannot import /nonexistent/lib.blang: "));
+        assert_eq!(cycle, format!("cannot import {}: import cycle", cycle_a));
+        assert_eq!(
+            failed,
+            format!("cannot import {}: division by zero", broken)
+        );
+
+        let lexer = Lexer::new("import \"lib/math.blang\"".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.parse()[0].to_string(), "import \"lib/math.blang\"");
+    }
+
+    #[test]
+    fn test_eval_else_if() {
+        let test_inputs = vec![
+            ("let x = 1; if (x == 1) { \"one\" } else if (x == 2) { \"two\" } else { \"many\" }", "\"one\""),
+            ("let x = 2; if (x == 1) { \"one\" } else if (x == 2) { \"two\" } else { \"many\" }", "\"two\""),
+            ("let x = 3; if (x == 1) { \"one\" } else if (x == 2) { \"two\" } else { \"many\" }", "\"many\""),
+            ("if (false) { 1 } else if (false) { 2 }", "null"),
+            // Conditions after the taken branch never run
+            ("if (true) { 1 } else if (missing) { 2 } else if (1 / 0) { 3 }", "1"),
+            ("let f = fn(n) { if (n < 0) { -1 } else if (n == 0) { 0 } else { 1 } }; [f(-3), f(0), f(3)]", "[-1, 0, 1]"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        // Count the calls made from conditions to see which ones ran
+        let input = "let check = fn(n) { n > 0 };
+            if (check(1)) { 1 } else if (check(2)) { 2 } else if (check(3)) { 3 }";
+        let lexer = Lexer::new(input.to_string());
         let mut parser = Parser::new(lexer);
         let mut program = Program::new(parser.parse());
-        let obj = program.eval();
-        assert!(obj.is_some());
-        let unwrapped = obj.unwrap();
+        program.profiling = true;
+        assert_eq!(program.eval().unwrap().inspect(), "1");
+        assert_eq!(program.profile()["CallExpression"], 1);
 
-        assert_eq!(unwrapped.inspect(), test_input.1);
+        let input = "let check = fn(n) { n > 1 };
+            if (check(1)) { 1 } else if (check(2)) { 2 } else if (check(3)) { 3 }";
+        let lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        program.profiling = true;
+        assert_eq!(program.eval().unwrap().inspect(), "2");
+        assert_eq!(program.profile()["CallExpression"], 2);
+
+        let lexer = Lexer::new("if (a) { 1 } else if (b) { 2 } else { 3 }".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(
+            parser.parse()[0].to_string(),
+            "if (a) { 1; } else if (b) { 2; } else { 3; }"
+        );
     }
 
     #[test]
-    fn test_update_env() {
+    fn test_eval_if_then_else() {
         let test_inputs = vec![
-            ("let x = 5; x", "x", 5),
-            ("10; let y = 15;", "y", 15),
-            ("if (true) { let a = 5; return a;}", "a", 5),
-            ("if (true) { let a = 1; let b = 2; return 10;}", "b", 2),
+            ("let x = 5; if x > 0 then 1 else -1", "1"),
+            ("let x = -5; if x > 0 then 1 else -1", "-1"),
+            ("if (1 < 2) then \"yes\" else \"no\"", "\"yes\""),
+            ("if false then 1", "null"),
+            ("let y = if 2 > 1 then 10 else 20; y * 2", "20"),
+            ("if true then if false then 1 else 2 else 3", "2"),
+            ("let sign = fn(n) { if n < 0 then -1 else if n == 0 then 0 else 1 }; [sign(-4), sign(0), sign(9)]", "[-1, 0, 1]"),
+            ("if 1 == 1 then { let a = 2; a * 3 } else 0", "6"),
         ];
         for test_input in test_inputs {
-            test_update_env_individual(test_input);
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("if x > 0 then 1 else -1".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(
+            parser.parse()[0].to_string(),
+            "if ((x > 0)) { 1 } else { (-1) }"
+        );
+    }
+
+    #[test]
+    fn test_eval_coalesce() {
+        let test_inputs = vec![
+            ("if (false) { 1 } ?? 5", "5"),
+            ("3 ?? 5", "3"),
+            ("false ?? 5", "false"),
+            ("0 ?? 5", "0"),
+            ("missing ?? 5", "5"),
+            ("1 / 0 ?? \"fallback\"", "\"fallback\""),
+            ("let h = {\"a\": 1}; h[\"b\"] ?? h[\"a\"] ?? 0", "1"),
+            ("missing ?? also_missing ?? 7", "7"),
+            ("1 ?? 2 + 3", "1"),
+            // The right side is only evaluated when it is needed
+            ("3 ?? missing", "3"),
+            ("missing ?? missing_too", "unknown identifier: missing_too"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
         }
     }
 
+    #[test]
+    fn test_eval_logical_operators() {
+        let test_inputs = vec![
+            ("5 > 3 && 2 < 4", true),
+            ("5 > 3 && 2 > 4", false),
+            ("5 < 3 || 2 < 4", true),
+            ("false || false", false),
+            ("1 && \"s\"", true),
+            ("if (false) { 1 } || false", false),
+            ("!true || 1 == 1 && 2 != 2", false),
+            ("true && false || true", true),
+            ("true || false && false", true),
+            // The right side is skipped once the left side decides
+            ("false && missing", false),
+            ("true || missing", true),
+        ];
+        for test_input in test_inputs {
+            test_eval_boolean(test_input);
+        }
+        test_eval_error((
+            "true && missing",
+            ErrorKind::UndefinedIdentifier,
+            "unknown identifier: missing",
+        ));
+    }
+
+    #[test]
+    fn test_short_circuit_side_effects() {
+        // read_line only takes a line from the input when it is evaluated
+        set_input(Box::new(Cursor::new("first\nsecond\n")));
+        let lexer = Lexer::new(
+            "let a = true || read_line(); let b = false && read_line(); let c = false || read_line(); [a, b, c, read_line()]"
+                .to_string(),
+        );
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        assert_eq!(
+            program.eval().unwrap().inspect(),
+            "[true, false, true, \"second\"]"
+        );
+    }
+
+    #[test]
+    fn test_eval_prefix_before_call() {
+        let test_inputs = vec![
+            ("let add = fn(x) { x + 1 }; -add(5)", "-6"),
+            ("let f = fn(x) { x }; !f(false)", "true"),
+            ("let a = fn(...xs) { xs }(1, 2); -a[1] * 3", "-6"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_eval_negative_elements() {
+        let test_inputs = vec![
+            ("[-1, -2]", "[-1, -2]"),
+            ("[1, -2, - 3]", "[1, -2, -3]"),
+            ("let f = fn(x) { x }; f(-1)", "-1"),
+            ("let sub = fn(a, b) { a - b }; sub(-1, -2)", "1"),
+            ("{-1: -2}[-1]", "-2"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_eval_template() {
+        let test_inputs =
+            vec![
+            (
+                "let name = \"Ann\"; let age = 40; `Hello ${name}, you are ${age + 1} years old`",
+                "\"Hello Ann, you are 41 years old\"",
+            ),
+            ("`${[1, \"a\"]} and ${{\"k\": 2}[\"k\"]}`", "\"[1, \\\"a\\\"] and 2\""),
+            ("let f = fn(x) { `<${x}>` }; f(`${1}${2}`)", "\"<12>\""),
+            ("`cost: \\${5} ${\"}\"}`", "\"cost: ${5} }\""),
+            ("`${if (false) { 1 }}`", "\"null\""),
+            ("`a ${missing} b`", "unknown identifier: missing"),
+            ("`Hello world`", "\"Hello world\""),
+            ("``", "\"\""),
+            ("len(`1 + 1`)", "5"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("`a\\`b ${x+1}\\n`".to_string());
+        let mut parser = Parser::new(lexer);
+        let program = Program::new(parser.parse());
+        assert_eq!(program.statements[0].to_string(), "`a\\`b ${(x + 1)}\\n`");
+
+        // Bad expressions are reported at the template, not raised
+        let test_inputs = vec![
+            ("let s = `a ${} b`", "EMPTY TEMPLATE EXPRESSION", 8),
+            (
+                "1; `${1; 2}`",
+                "TEMPLATE EXPRESSION MUST BE A SINGLE EXPRESSION",
+                3,
+            ),
+            (
+                "`${let x = 1}`",
+                "TEMPLATE EXPRESSION MUST BE A SINGLE EXPRESSION",
+                0,
+            ),
+            ("`${1 +}`", "unexpected EOF", 0),
+        ];
+        for test_input in test_inputs {
+            let mut parser = Parser::new(Lexer::new(test_input.0.to_string()));
+            let error = parser.try_parse().err().unwrap();
+            assert_eq!(error, (test_input.1.to_string(), test_input.2));
+        }
+    }
+
+    #[test]
+    fn test_eval_concatenation() {
+        let test_inputs = vec![
+            ("[1, 2] + [3, 4]", "[1, 2, 3, 4]"),
+            ("[] + [1] + []", "[1]"),
+            ("let a = [1]; let b = a + [2]; [a, b]", "[[1], [1, 2]]"),
+            ("[\"x\"] + [[true]]", "[\"x\", [true]]"),
+            ("\"foo\" + \"bar\"", "\"foobar\""),
+            ("let s = \"a\"; s + s + s", "\"aaa\""),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        test_eval_error((
+            "[1, 2] + 3",
+            ErrorKind::TypeMismatch,
+            "type mismatch: ARRAY + INTEGER",
+        ));
+        test_eval_error((
+            "\"a\" + [1]",
+            ErrorKind::TypeMismatch,
+            "type mismatch: STRING + ARRAY",
+        ));
+        test_eval_error((
+            "[1] - [1]",
+            ErrorKind::TypeMismatch,
+            "type mismatch: ARRAY - ARRAY",
+        ));
+    }
+
+    #[test]
+    fn test_eval_comparisons() {
+        let test_inputs = vec![
+            ("\"a\" < \"b\"", true),
+            ("\"b\" < \"a\"", false),
+            ("\"apple\" < \"banana\"", true),
+            ("\"apple\" > \"app\"", true),
+            ("\"Zebra\" < \"apple\"", true),
+            ("\"a\" <= \"a\"", true),
+            ("\"b\" >= \"c\"", false),
+            ("\"a\" == \"a\"", true),
+            ("\"a\" != \"a\"", false),
+            ("3 <= 3", true),
+            ("4 <= 3", false),
+            ("3 >= 4", false),
+            ("1 + 2 >= 3 == true", true),
+        ];
+        for test_input in test_inputs {
+            test_eval_boolean(test_input);
+        }
+        test_eval_error((
+            "\"a\" < 1",
+            ErrorKind::TypeMismatch,
+            "type mismatch: STRING < INTEGER",
+        ));
+    }
+
+    #[test]
+    fn test_eval_power() {
+        let test_inputs = vec![
+            ("2 ** 10", "1024"),
+            ("2 ** 3 ** 2", "512"),
+            ("-2 ** 2", "-4"),
+            ("(-2) ** 3", "-8"),
+            ("2 * 3 ** 2", "18"),
+            ("5 ** 0", "1"),
+            ("0 ** 0", "1"),
+            ("1 ** 9223372036854775807", "1"),
+            ("2 ** 63", "integer overflow: 2 ** 63"),
+            (
+                "2 ** -1",
+                "negative exponent: 2 ** -1 has no integer result",
+            ),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("a ** b ** c * d".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.parse()[0].to_string(), "((a ** (b ** c)) * d)");
+    }
+
+    #[test]
+    fn test_eval_let_in() {
+        let test_inputs = vec![
+            ("let x = 5 in x * 2", "10"),
+            ("let y = let x = 2 in x + 1; y", "3"),
+            ("let x = 1; let y = let x = 10 in x; x + y", "11"),
+            ("let x = 1 in let y = 2 in x + y", "3"),
+            ("1 + let x = 2 in x", "3"),
+            ("let f = fn(n) { let m = n * 2 in m + 1 }; f(4)", "9"),
+            ("let x = 5 in x; x", "unknown identifier: x"),
+            ("let x = foo in 1", "unknown identifier: foo"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("let x = 1 in x + 1".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.parse()[0].to_string(), "let x = 1 in (x + 1)");
+    }
+
+    #[test]
+    fn test_eval_multiple_return_values() {
+        let test_inputs = vec![
+            ("let f = fn(x) { return x, x * 2; }; f(3)", "[3, 6]"),
+            (
+                "let divmod = fn(a, b) { return a / b, a - (a / b) * b; }; let r = divmod(17, 5); r[0] + r[1]",
+                "5",
+            ),
+            ("let f = fn(x) { return x; }; f(3)", "3"),
+            ("let f = fn() { return 1, \"two\", [3]; }; f()[2][0]", "3"),
+            ("let f = fn() { return 1, foo; }; f()", "unknown identifier: foo"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("return a, b + 1;".to_string());
+        let mut parser = Parser::new(lexer);
+        assert_eq!(parser.parse()[0].to_string(), "return [a, (b + 1)];");
+    }
+
+    #[test]
+    fn test_eval_while() {
+        let test_inputs = vec![
+            ("let i = 0; while (i < 5) { let i = i + 1; }; i", "5"),
+            (
+                "let i = 0; let total = 0; while (i < 4) { let i = i + 1; let total = total + i; }; total",
+                "10",
+            ),
+            ("let i = 0; while (false) { let i = i + 1; }; i", "0"),
+            ("while (false) { 1 }", "null"),
+            ("let i = 0; do { let i = i + 1; } while (false); i", "1"),
+            ("let i = 0; do { let i = i + 1; } while (i < 3); i", "3"),
+            ("let i = 10; do { let i = i + 1; } while (i < 3); i", "11"),
+            ("while (x) { 1 }", "unknown identifier: x"),
+            ("let i = 0; while (i < 5) { let i = i + foo; }", "unknown identifier: foo"),
+            (
+                "let count = fn(n) { let i = 0; while (i < n) { let i = i + 1; }; i }; count(7)",
+                "7",
+            ),
+            ("let i = 0; while (true) { return 1; }", "1"),
+            (
+                "let f = fn(n) { let i = 0; while (i < 10) { let i = i + 1; if (i == n) { return i * 100; } }; -1 }; f(3)",
+                "300",
+            ),
+            (
+                "let f = fn(n) { let i = 0; while (i < 10) { let i = i + 1; if (i == n) { return i * 100; } }; -1 }; f(20)",
+                "-1",
+            ),
+            ("let f = fn() { do { return 2; } while (true); 3 }; f() + 1", "3"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("do { x; } while (x < 1); while (true) { 1; }".to_string());
+        let mut parser = Parser::new(lexer);
+        let statements = parser.parse();
+        assert_eq!(statements[0].to_string(), "do { x; } while ((x < 1))");
+        assert_eq!(statements[1].to_string(), "while (true) { 1; }");
+    }
+
+    #[test]
+    fn test_eval_untaken_if() {
+        let test_inputs = vec![
+            ("if (false) { 1 }", "null"),
+            ("let x = if (false) { 1 }; x", "null"),
+            ("let x = if (1 > 2) { 1 }; is_null(x)", "true"),
+            ("!if (false) { 1 }", "true"),
+            ("let f = fn() { if (false) { 1 } }; f()", "null"),
+            ("if (true) { if (false) { 1 } }", "null"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_eval_null_equality() {
+        // Passing a branchless if as an argument binds the parameter to null
+        let setup = "let eq = fn(a, b) { a == b }; let neq = fn(a, b) { a != b };";
+        let test_inputs = vec![
+            ("eq(if (false) { 1 }, if (false) { 1 })", true),
+            ("neq(if (false) { 1 }, if (false) { 1 })", false),
+            ("eq(if (false) { 1 }, 5)", false),
+            ("neq(if (false) { 1 }, 5)", true),
+            ("eq(5, if (false) { 1 })", false),
+            ("neq(5, if (false) { 1 })", true),
+            ("eq(if (false) { 1 }, true)", false),
+            ("neq(\"a\", if (false) { 1 })", true),
+        ];
+        for test_input in test_inputs {
+            let input = format!("{} {}", setup, test_input.0);
+            test_eval_boolean((&input, test_input.1));
+        }
+    }
+
+    #[test]
+    fn test_eval_shadowed_parameter() {
+        let test_inputs = vec![
+            ("fn(x) { let x = x + 1; x }(5)", "6"),
+            (
+                "let x = 10; let f = fn(x) { let x = x + 1; x }; [f(5), x]",
+                "[6, 10]",
+            ),
+            (
+                "let f = fn(x) { let y = x; let x = x * 2; [y, x] }; f(3)",
+                "[3, 6]",
+            ),
+            (
+                "let x = 1; let f = fn(x) { if (x > 0) { let x = x - 1; x } }; [f(5), x]",
+                "[4, 1]",
+            ),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+            assert!(!program.environment.has_key("y"));
+        }
+    }
+
+    #[test]
+    fn test_eval_function_equality() {
+        let test_inputs = vec![
+            ("let f = fn() {}; f == f", true),
+            ("let f = fn() {}; f != f", false),
+            ("let f = fn() {}; let g = f; f == g", true),
+            ("let f = fn() {}; let g = fn() {}; f == g", false),
+            ("let f = fn() {}; let g = fn() {}; f != g", true),
+            ("let id = fn(x) { x }; let f = fn() {}; id(f) == f", true),
+            ("let m = memoize(fn(x) { x }); m == m", true),
+            ("let f = fn(x) { x }; memoize(f) == memoize(f)", false),
+        ];
+        for test_input in test_inputs {
+            test_eval_boolean(test_input);
+        }
+    }
+
+    fn test_eval_boolean(test_input: (&str, bool)) {
+        let lexer = Lexer::new(test_input.0.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        let obj = program.eval();
+        assert!(obj.is_some());
+        let unwrapped = obj.unwrap();
+        assert_eq!(&unwrapped.type_(), &Type::BOOLEAN);
+        assert_eq!(
+            &unwrapped.downcast_ref::<Boolean>().unwrap().value,
+            &test_input.1
+        );
+    }
+
+    #[test]
+    fn test_eval_deeply_nested_blocks() {
+        // Builds `if (true) { if (true) { ... { 7 } } }` directly, since the
+        // parser itself recurses once per level. Evaluation runs on a small
+        // stack that recursive block evaluation would overflow.
+        let depth = 20_000;
+        let handle = std::thread::Builder::new()
+            .stack_size(1024 * 1024)
+            .spawn(move || {
+                let mut block = BlockStatement::new(
+                    Token::new(TokenType::LBRACE, Some("{")),
+                    vec![Box::new(ExpressionStatement::new(
+                        Token::new(TokenType::INT, Some("7")),
+                        Box::new(IntegerLiteralExpression::new(
+                            Token::new(TokenType::INT, Some("7")),
+                            7,
+                        )),
+                    ))],
+                );
+                for _ in 0..depth {
+                    let if_expr = IfExpression::new(
+                        Token::new(TokenType::IF, Some("if")),
+                        Box::new(BooleanExpression::new(
+                            Token::new(TokenType::TRUE, Some("true")),
+                            true,
+                        )),
+                        Box::new(block),
+                        None,
+                    );
+                    block = BlockStatement::new(
+                        Token::new(TokenType::LBRACE, Some("{")),
+                        vec![Box::new(ExpressionStatement::new(
+                            Token::new(TokenType::RBRACE, Some("}")),
+                            Box::new(if_expr),
+                        ))],
+                    );
+                }
+
+                let result = block.eval(&mut Environment::new()).unwrap().inspect();
+                // Dropping the tree is still recursive, so leak it instead
+                std::mem::forget(block);
+                return result;
+            })
+            .unwrap();
+        assert_eq!(handle.join().unwrap(), "7");
+    }
+
+    #[test]
+    fn test_eval_operator_methods() {
+        let test_inputs = vec![
+            (
+                "let v = {\"x\": 1, \"__add__\": fn(other) { other + 10 }}; v + 5",
+                15,
+            ),
+            (
+                "let v = {\"__sub__\": fn(other) { 100 - other }}; v - 1",
+                99,
+            ),
+            (
+                "let v = {\"__mul__\": fn(other) { other * other }}; v * 4 + 1",
+                17,
+            ),
+            (
+                "let half = fn(other) { other / 2 }; let v = {\"__div__\": half}; v / 8",
+                4,
+            ),
+        ];
+        for test_input in test_inputs {
+            test_eval_integer(test_input);
+        }
+    }
+
+    #[test]
+    fn test_eval_index_expression() {
+        let test_inputs = vec![
+            ("let f = fn(...xs) { xs }; f(1, 2, 3)[0]", "1"),
+            (
+                "let f = fn(...xs) { xs }; let a = f(1, 2, 3); a[1 + 1]",
+                "3",
+            ),
+            ("let f = fn(...xs) { xs }; f(1, 2, 3)[3]", "null"),
+            ("let f = fn(...xs) { xs }; f(1, 2, 3)[-1]", "null"),
+            ("{\"a\": 5, true: 6}[\"a\"]", "5"),
+            ("let h = {\"a\": 5, true: 6}; h[1 == 1]", "6"),
+            ("{\"a\": 5}[\"b\"]", "null"),
+            ("{\"a\": 5}[fn(x) { x }]", "unusable as hash key: FUNCTION"),
+            ("5[0]", "index operator not supported: INTEGER[INTEGER]"),
+        ];
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_eval_index_and_call_chains() {
+        let test_inputs = vec![
+            ("[[1, 2], [3, 4]][1][0]", 3),
+            (
+                "let matrix = [[1, 2], [3, 4]]; matrix[0][1] + matrix[1][1]",
+                6,
+            ),
+            ("let make = fn() { fn(b) { b * 10 } }; make()(3)", 30),
+            (
+                "let get_fns = fn() { [fn(x) { x * 2 }, fn(x) { x + 1 }] }; get_fns()[0](5)",
+                10,
+            ),
+            ("[fn() { [7, 8] }][0]()[1]", 8),
+            ("len([1, 2 * 3, [4]])", 3),
+        ];
+        for test_input in test_inputs {
+            test_eval_integer(test_input);
+        }
+    }
+
+    #[test]
+    fn test_if_expression_integer() {
+        let test_inputs = vec![
+            ("if (5 == 5) { 10; }", 10),
+            ("if (1 == 2) { 10; } else { 5; }", 5),
+            ("if (1) { 10; } else { 5; }", 10),
+            (
+                "let f = fn(x) { if (x) { 10 } else { 5 } }; f(if (false) { 1 })",
+                5,
+            ),
+        ];
+        for test_input in test_inputs {
+            test_eval_integer(test_input);
+        }
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let test_inputs = vec![
+            (
+                "5 + true",
+                ErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            (
+                "true - 10",
+                ErrorKind::TypeMismatch,
+                "type mismatch: BOOLEAN - INTEGER",
+            ),
+            (
+                "10; 5 + true; return 15;",
+                ErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            ("-true", ErrorKind::InvalidOperand, "invalid type: -BOOLEAN"),
+            (
+                "-(5 + true)",
+                ErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            (
+                "if (5 + true) { x }",
+                ErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            (
+                "foobar;",
+                ErrorKind::UndefinedIdentifier,
+                "unknown identifier: foobar",
+            ),
+            ("5 |> 3", ErrorKind::NotAFunction, "not a function: INTEGER"),
+            (
+                "{fn(x) { x }: 1}",
+                ErrorKind::UnusableHashKey,
+                "unusable as hash key: FUNCTION",
+            ),
+            (
+                "let x = foo; 5",
+                ErrorKind::UndefinedIdentifier,
+                "unknown identifier: foo",
+            ),
+            ("1 / 0", ErrorKind::DivisionByZero, "division by zero"),
+            (
+                "let zero = 5 - 5; 10 / zero + 1",
+                ErrorKind::DivisionByZero,
+                "division by zero",
+            ),
+            (
+                "if (true) { let a = foo; 5 }",
+                ErrorKind::UndefinedIdentifier,
+                "unknown identifier: foo",
+            ),
+            (
+                "let f = fn() { let a = 1 + true; 2 }; f()",
+                ErrorKind::TypeMismatch,
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            (
+                "{\"x\": 1} + 1",
+                ErrorKind::UnknownOperator,
+                "unsupported operator + for HASH: no __add__ key",
+            ),
+            (
+                "{\"__add__\": 5} + 1",
+                ErrorKind::NotAFunction,
+                "not a function: INTEGER",
+            ),
+            (
+                "{} == {}",
+                ErrorKind::TypeMismatch,
+                "type mismatch: HASH == HASH",
+            ),
+            (
+                "let f = fn() {}; f + f",
+                ErrorKind::TypeMismatch,
+                "type mismatch: FUNCTION + FUNCTION",
+            ),
+            (
+                "let f = fn(a) { a + 1 }; f(if (false) { 1 })",
+                ErrorKind::TypeMismatch,
+                "type mismatch: NULL + INTEGER",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            test_eval_error(test_input);
+        }
+    }
+
+    fn test_eval_error(test_input: (&str, ErrorKind, &str)) {
+        let lexer = Lexer::new(test_input.0.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        let obj = program.eval();
+        assert!(obj.is_some());
+        let unwrapped = obj.unwrap();
+
+        assert_eq!(
+            unwrapped.downcast_ref::<Error>().unwrap().kind,
+            test_input.1
+        );
+        assert_eq!(unwrapped.inspect(), test_input.2);
+    }
+
+    #[test]
+    fn test_update_env() {
+        let test_inputs = vec![
+            ("let x = 5; x", "x", 5),
+            ("10; let y = 15;", "y", 15),
+            ("if (true) { let a = 5; return a;}", "a", 5),
+            ("if (true) { let a = 1; let b = 2; return 10;}", "b", 2),
+        ];
+        for test_input in test_inputs {
+            test_update_env_individual(test_input);
+        }
+    }
+
+    #[test]
+    fn test_block_update_env_error() {
+        let lexer = Lexer::new("if (true) { let a = 1; foo; let b = 2; }".to_string());
+        let mut parser = Parser::new(lexer);
+        let program = Program::new(parser.parse());
+        let if_expr = program.statements[0]
+            .downcast_ref::<ExpressionStatement>()
+            .unwrap()
+            .expression
+            .downcast_ref::<IfExpression>()
+            .unwrap();
+
+        let mut env = Environment::new();
+        let result = if_expr.consequence.update_env(&mut env);
+        assert_eq!(result.err().unwrap().inspect(), "unknown identifier: foo");
+        assert!(env.has_key("a"));
+        assert!(!env.has_key("b"));
+    }
+
     fn test_update_env_individual(test_input: (&str, &str, i64)) {
         let lexer = Lexer::new(test_input.0.to_string());
         let mut parser = Parser::new(lexer);
@@ -0,0 +1,1134 @@
+use crate::environment::Environment;
+use crate::runner::eval_source;
+use crate::statements::{check_collection_size, is_truthy};
+use crate::types::{
+    Arity, Array, Boolean, Builtin, BuiltinFunction, ComposedFunction, Error, ErrorKind, Hash,
+    Integer, MemoizedFunction, Null, Object, StringLiteral, Type,
+};
+use lazy_static::lazy_static;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::rc::Rc;
+
+pub struct BuiltinDef {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub arity: Arity,
+    pub function: BuiltinFunction,
+}
+
+lazy_static! {
+    static ref BUILTINS: Vec<BuiltinDef> = vec![
+        BuiltinDef {
+            name: "len",
+            description: "length of a string, array or hash",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(len),
+        },
+        BuiltinDef {
+            name: "reverse",
+            description: "new array or string with the elements in reverse order",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(reverse),
+        },
+        BuiltinDef {
+            name: "push",
+            description: "new array with a value added to the end",
+            arity: Arity::Exactly(2),
+            function: BuiltinFunction::WithEnv(push),
+        },
+        BuiltinDef {
+            name: "puts",
+            description: "print each argument on its own line",
+            arity: Arity::AtLeast(0),
+            function: BuiltinFunction::Plain(puts),
+        },
+        BuiltinDef {
+            name: "format",
+            description: "string with each {} in the template replaced by an argument",
+            arity: Arity::AtLeast(1),
+            function: BuiltinFunction::Plain(format),
+        },
+        BuiltinDef {
+            name: "compose",
+            description: "function that calls the second argument, then the first",
+            arity: Arity::Exactly(2),
+            function: BuiltinFunction::Plain(compose),
+        },
+        BuiltinDef {
+            name: "keys",
+            description: "array of a hash's keys in insertion order",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(keys),
+        },
+        BuiltinDef {
+            name: "values",
+            description: "array of a hash's values in insertion order",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(values),
+        },
+        BuiltinDef {
+            name: "clone",
+            description: "independent deep copy of the argument",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(clone),
+        },
+        BuiltinDef {
+            name: "has_key",
+            description: "true if the hash has a value for the key",
+            arity: Arity::Exactly(2),
+            function: BuiltinFunction::Plain(has_key),
+        },
+        BuiltinDef {
+            name: "assert",
+            description: "error, with an optional message, unless the condition is truthy",
+            arity: Arity::Between(1, 2),
+            function: BuiltinFunction::Plain(assert),
+        },
+        BuiltinDef {
+            name: "is_int",
+            description: "true if the argument is an integer",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(is_int),
+        },
+        BuiltinDef {
+            name: "is_string",
+            description: "true if the argument is a string",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(is_string),
+        },
+        BuiltinDef {
+            name: "is_array",
+            description: "true if the argument is an array",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(is_array),
+        },
+        BuiltinDef {
+            name: "is_null",
+            description: "true if the argument is null",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(is_null),
+        },
+        BuiltinDef {
+            name: "is_fn",
+            description: "true if the argument can be called",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(is_fn),
+        },
+        BuiltinDef {
+            name: "typeof",
+            description: "name of the argument's type",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(type_of),
+        },
+        BuiltinDef {
+            name: "memoize",
+            description: "wrap a function with a cache of its results",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::Plain(memoize),
+        },
+        BuiltinDef {
+            name: "read_line",
+            description: "next line of input without its newline, or null at the end",
+            arity: Arity::Exactly(0),
+            function: BuiltinFunction::Plain(read_line),
+        },
+        BuiltinDef {
+            name: "exit",
+            description: "stop the process with the given exit code, 0 by default",
+            arity: Arity::Between(0, 1),
+            function: BuiltinFunction::Plain(exit),
+        },
+        BuiltinDef {
+            name: "random",
+            description: "random integer from 0 up to but not including n",
+            arity: Arity::Exactly(1),
+            function: BuiltinFunction::WithEnv(random),
+        },
+        BuiltinDef {
+            name: "breakpoint",
+            description: "pause and inspect variables, :continue or an empty line resumes",
+            arity: Arity::Exactly(0),
+            function: BuiltinFunction::WithEnv(breakpoint),
+        },
+    ];
+}
+
+thread_local! {
+    // Where read_line reads from, stdin unless replaced with `set_input`. The
+    // lock shares stdin's own buffer instead of reading ahead into another.
+    static INPUT: RefCell<Box<dyn BufRead>> = RefCell::new(Box::new(stdin().lock()));
+    // Where puts writes to, stdout unless replaced with `set_output`
+    static OUTPUT: RefCell<Box<dyn Write>> = RefCell::new(Box::new(stdout()));
+}
+
+// Replaces the input read_line reads from on this thread, e.g. with canned
+// lines in tests.
+pub fn set_input(reader: Box<dyn BufRead>) {
+    INPUT.with(|input| *input.borrow_mut() = reader);
+}
+
+// Reads a line from the input read_line uses. The REPL reads its own lines
+// through here too, so both take turns on the same stream.
+pub fn read_input(line: &mut String) -> std::io::Result<usize> {
+    return INPUT.with(|input| input.borrow_mut().read_line(line));
+}
+
+// Replaces the output puts and breakpoint write to on this thread, returning
+// the one it replaced so it can be put back.
+pub fn set_output(writer: Box<dyn Write>) -> Box<dyn Write> {
+    return OUTPUT.with(|output| std::mem::replace(&mut *output.borrow_mut(), writer));
+}
+
+pub fn list_builtins() -> &'static [BuiltinDef] {
+    return &BUILTINS;
+}
+
+pub fn get_builtin(name: &str) -> Option<Box<dyn Object>> {
+    let def = BUILTINS.iter().find(|def| def.name == name)?;
+
+    return Some(Box::new(Builtin {
+        name: name.to_string(),
+        arity: def.arity,
+        function: def.function,
+    }));
+}
+
+fn type_predicate(args: Vec<Box<dyn Object>>, types: &[Type]) -> Box<dyn Object> {
+    return Box::new(Boolean {
+        value: types.contains(&args[0].type_()),
+    });
+}
+
+fn len(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let string = args[0].downcast_ref::<StringLiteral>();
+    if string.is_some() {
+        return Box::new(Integer {
+            value: string.unwrap().value.chars().count() as i64,
+        });
+    }
+
+    let array = args[0].downcast_ref::<Array>();
+    if array.is_some() {
+        return Box::new(Integer {
+            value: array.unwrap().elements.len() as i64,
+        });
+    }
+
+    let hash = args[0].downcast_ref::<Hash>();
+    if hash.is_some() {
+        return Box::new(Integer {
+            value: hash.unwrap().pairs.len() as i64,
+        });
+    }
+
+    return Box::new(Error {
+        kind: ErrorKind::InvalidArgument,
+        data: None,
+        message: format!("argument to len not supported, got {:?}", args[0].type_()),
+    });
+}
+
+fn reverse(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let string = args[0].downcast_ref::<StringLiteral>();
+    if string.is_some() {
+        return Box::new(StringLiteral {
+            value: string.unwrap().value.chars().rev().collect(),
+        });
+    }
+
+    let array = args[0].downcast_ref::<Array>();
+    if array.is_some() {
+        return Box::new(Array {
+            elements: array
+                .unwrap()
+                .elements
+                .iter()
+                .rev()
+                .map(|x| x.get_box())
+                .collect(),
+        });
+    }
+
+    return Box::new(Error {
+        kind: ErrorKind::InvalidArgument,
+        data: None,
+        message: format!(
+            "argument to reverse not supported, got {:?}",
+            args[0].type_()
+        ),
+    });
+}
+
+fn push(args: Vec<Box<dyn Object>>, env: &Environment) -> Box<dyn Object> {
+    let array = args[0].downcast_ref::<Array>();
+    if array.is_none() {
+        return Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!("argument to push must be ARRAY, got {:?}", args[0].type_()),
+        });
+    }
+    let too_large = check_collection_size(array.unwrap().elements.len() + 1, env);
+    if too_large.is_some() {
+        return too_large.unwrap();
+    }
+
+    let mut elements: Vec<Box<dyn Object>> = array
+        .unwrap()
+        .elements
+        .iter()
+        .map(|x| x.get_box())
+        .collect();
+    elements.push(args[1].get_box());
+    return Box::new(Array { elements });
+}
+
+// puts shows values as text for people, so strings print raw rather than in
+// the quoted form the REPL echoes.
+pub fn puts_lines(args: &[Box<dyn Object>]) -> Vec<String> {
+    return args.iter().map(|arg| arg.display()).collect();
+}
+
+fn puts(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    OUTPUT.with(|output| {
+        let mut output = output.borrow_mut();
+        for line in puts_lines(&args) {
+            let _ = writeln!(output, "{}", line);
+        }
+    });
+    return Box::new(Null {});
+}
+
+fn format(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let template = args[0].downcast_ref::<StringLiteral>();
+    if template.is_none() {
+        return Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!(
+                "first argument to format must be STRING, got {:?}",
+                args[0].type_()
+            ),
+        });
+    }
+
+    let pieces: Vec<&str> = template.unwrap().value.split("{}").collect();
+    let values = &args[1..];
+    if pieces.len() - 1 != values.len() {
+        return Box::new(Error {
+            kind: ErrorKind::WrongArgumentCount,
+            data: None,
+            message: format!(
+                "format expected {} arguments for its placeholders, got {}",
+                pieces.len() - 1,
+                values.len()
+            ),
+        });
+    }
+
+    let mut formatted = pieces[0].to_string();
+    for (value, piece) in values.iter().zip(&pieces[1..]) {
+        formatted.push_str(&value.display());
+        formatted.push_str(piece);
+    }
+    return Box::new(StringLiteral { value: formatted });
+}
+
+fn hash_argument<'a>(name: &str, args: &'a [Box<dyn Object>]) -> Result<&'a Hash, Box<dyn Object>> {
+    let hash = args[0].downcast_ref::<Hash>();
+    if hash.is_none() {
+        return Err(Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!(
+                "argument to {} must be HASH, got {:?}",
+                name,
+                args[0].type_()
+            ),
+        }));
+    }
+    return Ok(hash.unwrap());
+}
+
+fn keys(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let hash = match hash_argument("keys", &args) {
+        Ok(hash) => hash,
+        Err(error) => return error,
+    };
+
+    return Box::new(Array {
+        elements: hash.pairs.iter().map(|(k, _)| k.get_box()).collect(),
+    });
+}
+
+fn values(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let hash = match hash_argument("values", &args) {
+        Ok(hash) => hash,
+        Err(error) => return error,
+    };
+
+    return Box::new(Array {
+        elements: hash.pairs.iter().map(|(_, v)| v.get_box()).collect(),
+    });
+}
+
+// get_box already copies arrays and hashes all the way down.
+fn clone(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return args[0].get_box();
+}
+
+fn has_key(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let hash = args[0].downcast_ref::<Hash>();
+    if hash.is_none() {
+        return Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!(
+                "first argument to has_key must be HASH, got {:?}",
+                args[0].type_()
+            ),
+        });
+    }
+    if Hash::hash_key(args[1].as_ref()).is_none() {
+        return Box::new(Error {
+            kind: ErrorKind::UnusableHashKey,
+            data: None,
+            message: format!("unusable as hash key: {:?}", args[1].type_()),
+        });
+    }
+
+    return Box::new(Boolean {
+        value: hash.unwrap().get(args[1].as_ref()).is_some(),
+    });
+}
+
+fn assert(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    if is_truthy(args[0].as_ref()) {
+        return Box::new(Null {});
+    }
+
+    if args.len() == 2 {
+        return Box::new(Error {
+            kind: ErrorKind::AssertionFailed,
+            data: None,
+            message: format!("assertion failed: {}", args[1].display()),
+        });
+    }
+    return Box::new(Error {
+        kind: ErrorKind::AssertionFailed,
+        data: None,
+        message: "assertion failed".to_string(),
+    });
+}
+
+fn compose(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    for arg in &args {
+        if arg.type_() != Type::FUNCTION && arg.type_() != Type::BUILTIN {
+            return Box::new(Error {
+                kind: ErrorKind::InvalidArgument,
+                data: None,
+                message: format!(
+                    "arguments to compose must be FUNCTION or BUILTIN, got {:?}",
+                    arg.type_()
+                ),
+            });
+        }
+    }
+
+    let mut args = args.into_iter();
+    return Box::new(ComposedFunction {
+        outer: args.next().unwrap(),
+        inner: args.next().unwrap(),
+    });
+}
+
+fn is_int(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return type_predicate(args, &[Type::INTEGER]);
+}
+
+fn is_string(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return type_predicate(args, &[Type::STRING]);
+}
+
+fn is_array(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return type_predicate(args, &[Type::ARRAY]);
+}
+
+fn is_null(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return type_predicate(args, &[Type::NULL]);
+}
+
+fn is_fn(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return type_predicate(args, &[Type::FUNCTION, Type::BUILTIN]);
+}
+
+fn type_of(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    return Box::new(StringLiteral {
+        value: args[0].type_().name().to_string(),
+    });
+}
+
+fn memoize(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    if args[0].type_() != Type::FUNCTION {
+        return Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!(
+                "argument to memoize must be FUNCTION, got {:?}",
+                args[0].type_()
+            ),
+        });
+    }
+
+    return Box::new(MemoizedFunction {
+        function: args[0].get_box(),
+        cache: Rc::new(RefCell::new(HashMap::new())),
+    });
+}
+
+fn read_line(_args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let mut line = String::new();
+    let read = read_input(&mut line);
+    match read {
+        Ok(0) => return Box::new(Null {}),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            return Box::new(StringLiteral { value: line });
+        }
+        Err(error) => {
+            return Box::new(Error {
+                kind: ErrorKind::InvalidArgument,
+                data: None,
+                message: format!("could not read input: {}", error),
+            });
+        }
+    }
+}
+
+fn exit(args: Vec<Box<dyn Object>>) -> Box<dyn Object> {
+    let mut code: i32 = 0;
+    if args.len() == 1 {
+        let integer = args[0].downcast_ref::<Integer>();
+        if integer.is_none() {
+            return Box::new(Error {
+                kind: ErrorKind::InvalidArgument,
+                data: None,
+                message: format!(
+                    "argument to exit must be INTEGER, got {:?}",
+                    args[0].type_()
+                ),
+            });
+        }
+        let value = i32::try_from(integer.unwrap().value);
+        if value.is_err() {
+            return Box::new(Error {
+                kind: ErrorKind::InvalidArgument,
+                data: None,
+                message: format!("exit code out of range: {}", integer.unwrap().value),
+            });
+        }
+        code = value.unwrap();
+    }
+    std::process::exit(code);
+}
+
+fn random(args: Vec<Box<dyn Object>>, env: &Environment) -> Box<dyn Object> {
+    let integer = args[0].downcast_ref::<Integer>();
+    if integer.is_none() {
+        return Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!(
+                "argument to random must be INTEGER, got {:?}",
+                args[0].type_()
+            ),
+        });
+    }
+    let n = integer.unwrap().value;
+    if n <= 0 {
+        return Box::new(Error {
+            kind: ErrorKind::InvalidArgument,
+            data: None,
+            message: format!("argument to random must be positive, got {}", n),
+        });
+    }
+
+    return Box::new(Integer {
+        value: (env.next_random() % n as u64) as i64,
+    });
+}
+
+// Pauses at a breakpoint, evaluating each line read from the input against
+// a copy of `env` until an empty line, `:continue` or the end of the input.
+// Bindings made while paused don't leak back into the program.
+fn breakpoint(_args: Vec<Box<dyn Object>>, env: &Environment) -> Box<dyn Object> {
+    let mut scope = env.get_copy();
+    write_output("breakpoint: inspect with expressions, :continue to resume");
+    loop {
+        OUTPUT.with(|output| {
+            let mut output = output.borrow_mut();
+            let _ = write!(output, "(debug) ");
+            let _ = output.flush();
+        });
+        let mut line = String::new();
+        let read = read_input(&mut line);
+        if read.is_err() || read.unwrap() == 0 {
+            break;
+        }
+        let line = line.trim();
+        if line.is_empty() || line == ":continue" {
+            break;
+        }
+
+        let result = eval_source(line, &mut scope);
+        if result.is_some() {
+            write_output(&result.unwrap().inspect());
+        }
+    }
+    return Box::new(Null {});
+}
+
+fn write_output(line: &str) {
+    OUTPUT.with(|output| {
+        let _ = writeln!(output.borrow_mut(), "{}", line);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parser;
+    use crate::lexer::Lexer;
+    use crate::program::Program;
+    use crate::session::Session;
+    use std::io::Cursor;
+
+    fn eval_input(input: &str) -> Box<dyn Object> {
+        let lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        return program.eval().unwrap();
+    }
+
+    #[test]
+    fn test_type_predicates() {
+        let test_inputs = vec![
+            ("is_int(5)", true),
+            ("is_int(\"5\")", false),
+            ("is_int(true)", false),
+            ("is_string(\"five\")", true),
+            ("is_string(5)", false),
+            ("let f = fn(...xs) { xs }; is_array(f(1, 2))", true),
+            ("is_array(5)", false),
+            ("is_null(if (false) { 1 })", true),
+            ("is_null(0)", false),
+            ("is_fn(fn(x) { x })", true),
+            ("let f = fn(x) { x }; is_fn(f)", true),
+            ("is_fn(is_fn)", true),
+            ("is_fn(5)", false),
+        ];
+
+        for test_input in test_inputs {
+            let obj = eval_input(test_input.0);
+            assert_eq!(obj.type_(), Type::BOOLEAN, "{}", test_input.0);
+            assert_eq!(
+                obj.downcast_ref::<Boolean>().unwrap().value,
+                test_input.1,
+                "{}",
+                test_input.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_line() {
+        set_input(Box::new(Cursor::new("alice\r\nbob\n\nlast")));
+        let test_inputs = vec![
+            ("let name = read_line(); \"hi \" + name", "\"hi alice\""),
+            ("read_line()", "\"bob\""),
+            ("read_line()", "\"\""),
+            ("read_line()", "\"last\""),
+            ("read_line()", "null"),
+            ("read_line() ?? \"done\"", "\"done\""),
+            (
+                "read_line(1)",
+                "wrong number of arguments to read_line: expected 0, got 1",
+            ),
+        ];
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    // A buffer the test can still read after handing it to set_output.
+    #[derive(Clone)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            return self.0.borrow_mut().write(buf);
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_random_arguments() {
+        let test_inputs = vec![
+            ("random(1)", "0"),
+            (
+                "random(\"6\")",
+                "argument to random must be INTEGER, got STRING",
+            ),
+            ("random(0)", "argument to random must be positive, got 0"),
+            (
+                "random()",
+                "wrong number of arguments to random: expected 1, got 0",
+            ),
+        ];
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        assert!(get_builtin("breakpoint").is_some());
+
+        let output = Captured(Rc::new(RefCell::new(vec![])));
+        set_output(Box::new(output.clone()));
+        set_input(Box::new(Cursor::new(
+            "x + 1\nlet y = x * 2\ny\nfoo\n:continue\nx\n",
+        )));
+        let result = eval_input("let x = 5; breakpoint(); let z = x + 10; z");
+        assert_eq!(result.inspect(), "15");
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            concat!(
+                "breakpoint: inspect with expressions, :continue to resume\n",
+                "(debug) 6\n(debug) (debug) 10\n(debug) unknown identifier: foo\n(debug) "
+            )
+        );
+
+        // Bindings made while paused stay there, and the rest of the input is
+        // left for the program
+        assert_eq!(eval_input("is_null(y)").inspect(), "unknown identifier: y");
+        assert_eq!(eval_input("read_line()").inspect(), "\"x\"");
+
+        // An empty line or the end of the input resumes too
+        set_input(Box::new(Cursor::new("\n")));
+        assert_eq!(eval_input("breakpoint(); 1").inspect(), "1");
+        set_input(Box::new(Cursor::new("")));
+        assert_eq!(eval_input("breakpoint(); 2").inspect(), "2");
+
+        // A statement that fails after pausing only pauses once
+        let output = Captured(Rc::new(RefCell::new(vec![])));
+        set_output(Box::new(output.clone()));
+        let mut session = Session::new();
+        assert!(session.eval("breakpoint() + missing").unwrap().is_error());
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone())
+                .unwrap()
+                .matches("breakpoint:")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_exit_arguments() {
+        // Only the failing calls can be tested, a valid one ends the process
+        let test_inputs = vec![
+            (
+                "exit(\"1\")",
+                "argument to exit must be INTEGER, got STRING",
+            ),
+            ("exit(5000000000)", "exit code out of range: 5000000000"),
+            (
+                "exit(1, 2)",
+                "wrong number of arguments to exit: expected 0 or 1, got 2",
+            ),
+        ];
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_arity() {
+        let test_inputs = vec![
+            (
+                "len()",
+                "wrong number of arguments to len: expected 1, got 0",
+            ),
+            (
+                "has_key({}, 1, 2)",
+                "wrong number of arguments to has_key: expected 2, got 3",
+            ),
+            (
+                "assert(true, \"a\", \"b\")",
+                "wrong number of arguments to assert: expected 1 or 2, got 3",
+            ),
+            // Checked however the builtin ends up being called
+            (
+                "compose(len, len)(\"a\", \"b\")",
+                "wrong number of arguments to len: expected 1, got 2",
+            ),
+            (
+                "\"a\" |> has_key",
+                "wrong number of arguments to has_key: expected 2, got 1",
+            ),
+            ("puts()", "null"),
+        ];
+        for test_input in test_inputs {
+            let obj = eval_input(test_input.0);
+            assert_eq!(obj.inspect(), test_input.1);
+        }
+        let obj = eval_input("is_int(1, 2)");
+        let error = obj.downcast_ref::<Error>().unwrap();
+        assert_eq!(error.kind, ErrorKind::WrongArgumentCount);
+
+        assert_eq!(Arity::Between(1, 3).describe(), "1 to 3");
+        assert!(Arity::AtLeast(1).accepts(5));
+        assert!(!Arity::Exactly(2).accepts(1));
+    }
+
+    #[test]
+    fn test_typeof() {
+        let test_inputs = vec![
+            ("typeof(5)", "INTEGER"),
+            ("typeof(true)", "BOOLEAN"),
+            ("typeof(\"five\")", "STRING"),
+            ("typeof(if (false) { 1 })", "NULL"),
+            ("typeof(fn(x) { x })", "FUNCTION"),
+            ("typeof(typeof)", "BUILTIN"),
+            ("let f = fn(...xs) { xs }; typeof(f(1, 2))", "ARRAY"),
+            ("typeof(typeof(5))", "STRING"),
+        ];
+
+        for test_input in test_inputs {
+            let obj = eval_input(test_input.0);
+            assert_eq!(
+                obj.downcast_ref::<StringLiteral>().unwrap().value,
+                test_input.1
+            );
+        }
+    }
+
+    fn cache_size(program: &Program, name: &str) -> usize {
+        let obj = program.environment.get(name);
+        return obj
+            .downcast_ref::<MemoizedFunction>()
+            .unwrap()
+            .cache
+            .borrow()
+            .len();
+    }
+
+    #[test]
+    fn test_memoize() {
+        let test_input = r#"
+            let fib = memoize(fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } });
+            fib(30)"#;
+        let lexer = Lexer::new(test_input.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        let obj = program.eval().unwrap();
+        assert_eq!(obj.inspect(), "832040");
+
+        // Each distinct argument is only evaluated once, rather than the
+        // 2.7 million calls the plain recursive definition would make.
+        assert_eq!(cache_size(&program, "fib"), 31);
+        assert_eq!(typeof_name(&program, "fib"), "FUNCTION");
+
+        let test_input = r#"
+            let first = memoize(fn(xs) { xs });
+            let collect = fn(...xs) { xs };
+            first(collect(1, 2));"#;
+        let lexer = Lexer::new(test_input.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        let obj = program.eval().unwrap();
+        assert_eq!(obj.inspect(), "[1, 2]");
+        assert_eq!(cache_size(&program, "first"), 0);
+    }
+
+    fn typeof_name(program: &Program, name: &str) -> &'static str {
+        return program.environment.get(name).type_().name();
+    }
+
+    #[test]
+    fn test_builtin_errors() {
+        let test_inputs = vec![
+            (
+                "is_int()",
+                "wrong number of arguments to is_int: expected 1, got 0",
+            ),
+            (
+                "is_string(1, 2)",
+                "wrong number of arguments to is_string: expected 1, got 2",
+            ),
+            ("is_int(foo)", "unknown identifier: foo"),
+            (
+                "len(1, 2)",
+                "wrong number of arguments to len: expected 1, got 2",
+            ),
+            ("len(5)", "argument to len not supported, got INTEGER"),
+            (
+                "memoize(5)",
+                "argument to memoize must be FUNCTION, got INTEGER",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_len() {
+        let test_inputs = vec![
+            ("len(\"four\")", "4"),
+            ("len(\"\\u{e9}t\\u{e9}\")", "3"),
+            ("let f = fn(...xs) { xs }; len(f(1, 2, 3))", "3"),
+            ("len([])", "0"),
+            ("len({})", "0"),
+            ("len({\"a\": 1, \"b\": 2})", "2"),
+            ("puts(\"hello\", 1)", "null"),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let test_inputs = vec![
+            ("reverse([1, 2, 3])", "[3, 2, 1]"),
+            ("reverse(\"abc\")", "\"cba\""),
+            ("reverse(\"héllo\")", "\"olléh\""),
+            ("reverse([])", "[]"),
+            ("let a = [1, [2, 3]]; reverse(a); a", "[1, [2, 3]]"),
+            (
+                "reverse(5)",
+                "argument to reverse not supported, got INTEGER",
+            ),
+            (
+                "reverse([1], [2])",
+                "wrong number of arguments to reverse: expected 1, got 2",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_push() {
+        let test_inputs = vec![
+            ("push([1, 2], 3)", "[1, 2, 3]"),
+            ("push([], [1])", "[[1]]"),
+            ("let a = [1]; push(a, 2); a", "[1]"),
+            ("push(1, 2)", "argument to push must be ARRAY, got INTEGER"),
+            (
+                "push([1])",
+                "wrong number of arguments to push: expected 2, got 1",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_keys_values() {
+        let test_inputs = vec![
+            ("keys({\"b\": 1, \"a\": 2})", "[\"b\", \"a\"]"),
+            ("values({\"b\": 1, \"a\": 2})", "[1, 2]"),
+            ("keys({2: true, 1: false, 2: 3})", "[2, 1]"),
+            ("values({2: true, 1: false, 2: 3})", "[3, false]"),
+            ("keys({})", "[]"),
+            ("keys(5)", "argument to keys must be HASH, got INTEGER"),
+            (
+                "values({}, {})",
+                "wrong number of arguments to values: expected 1, got 2",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_clone() {
+        let test_inputs = vec![
+            ("clone(5)", "5"),
+            ("clone(\"s\")", "\"s\""),
+            (
+                "clone({\"a\": [1, {\"b\": 2}]})",
+                "{\"a\": [1, {\"b\": 2}]}",
+            ),
+            (
+                "let a = [1, [2]]; let b = clone(a) + [3]; [a, b]",
+                "[[1, [2]], [1, [2], 3]]",
+            ),
+            ("let f = fn(x) { x * 2 }; clone(f)(4)", "8"),
+            (
+                "clone(1, 2)",
+                "wrong number of arguments to clone: expected 1, got 2",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+
+        // The copy shares nothing with the original
+        let original = Array {
+            elements: vec![Box::new(Array {
+                elements: vec![Box::new(Integer { value: 1 })],
+            })],
+        };
+        let mut copy = clone(vec![original.get_box()]);
+        let copy = copy.downcast_mut::<Array>().unwrap();
+        copy.elements[0].downcast_mut::<Array>().unwrap().elements[0] =
+            Box::new(Integer { value: 9 });
+        assert_eq!(copy.inspect(), "[[9]]");
+        assert_eq!(original.inspect(), "[[1]]");
+    }
+
+    #[test]
+    fn test_has_key() {
+        let test_inputs = vec![
+            ("has_key({\"a\": 1}, \"a\")", "true"),
+            ("has_key({\"a\": 1}, \"b\")", "false"),
+            ("has_key({1: if (false) { 1 }}, 1)", "true"),
+            ("let h = {true: 1}; has_key(h, 1 < 2)", "true"),
+            ("has_key({1: 2}, \"1\")", "false"),
+            ("{\"a\": 1}[\"b\"]", "null"),
+            ("has_key({}, fn() {})", "unusable as hash key: FUNCTION"),
+            (
+                "has_key([1], 0)",
+                "first argument to has_key must be HASH, got ARRAY",
+            ),
+            (
+                "has_key({})",
+                "wrong number of arguments to has_key: expected 2, got 1",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_assert() {
+        let test_inputs = vec![
+            ("assert(true)", "null"),
+            ("assert(1 < 2, \"ordering\")", "null"),
+            ("assert(0)", "null"),
+            ("assert(false)", "assertion failed"),
+            ("assert(if (false) { 1 })", "assertion failed"),
+            (
+                "assert(1 > 2, \"one is not greater\")",
+                "assertion failed: one is not greater",
+            ),
+            ("assert(false); 5", "assertion failed"),
+            (
+                "assert()",
+                "wrong number of arguments to assert: expected 1 or 2, got 0",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let test_inputs = vec![
+            ("format(\"{} + {} = {}\", 1, 2, 3)", "\"1 + 2 = 3\""),
+            ("format(\"no placeholders\")", "\"no placeholders\""),
+            ("format(\"{}{}\", \"a\", [1, true])", "\"a[1, true]\""),
+            (
+                "let name = \"x\"; format(\"{} is {}\", name, 5 * 2)",
+                "\"x is 10\"",
+            ),
+            (
+                "format(\"{} and {}\", 1)",
+                "format expected 2 arguments for its placeholders, got 1",
+            ),
+            (
+                "format(\"{}\", 1, 2)",
+                "format expected 1 arguments for its placeholders, got 2",
+            ),
+            (
+                "format(1)",
+                "first argument to format must be STRING, got INTEGER",
+            ),
+            (
+                "format()",
+                "wrong number of arguments to format: expected at least 1, got 0",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_compose() {
+        let test_inputs = vec![
+            (
+                "let inc = fn(x) { x + 1 }; let double = fn(x) { x * 2 }; compose(inc, double)(5)",
+                "11",
+            ),
+            (
+                "let inc = fn(x) { x + 1 }; let double = fn(x) { x * 2 }; let f = compose(double, inc); f(5)",
+                "12",
+            ),
+            ("compose(typeof, fn(x) { x })(5)", "\"INTEGER\""),
+            (
+                "let inc = fn(x) { x + 1 }; compose(inc, compose(inc, inc))(0)",
+                "3",
+            ),
+            ("let add = fn(a, b) { a + b }; compose(len, add)(\"ab\", \"c\")", "3"),
+            ("typeof(compose(len, len))", "\"FUNCTION\""),
+            (
+                "compose(len, 5)",
+                "arguments to compose must be FUNCTION or BUILTIN, got INTEGER",
+            ),
+            (
+                "compose(len)",
+                "wrong number of arguments to compose: expected 2, got 1",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            assert_eq!(eval_input(test_input.0).inspect(), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_get_builtin() {
+        assert!(get_builtin("is_int").is_some());
+        assert!(get_builtin("is_float").is_none());
+        assert_eq!(get_builtin("is_fn").unwrap().type_(), Type::BUILTIN);
+    }
+}
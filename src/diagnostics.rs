@@ -0,0 +1,148 @@
+use crate::ast::Parser;
+use crate::lexer::Lexer;
+use crate::token::TokenType;
+use crate::types::{Error, ErrorKind, Object};
+
+// The 1-based line and column of the character at byte `offset` in `source`.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    return (line, column);
+}
+
+// Formats `message` with the source line it refers to and a caret under the
+// column, e.g.
+//
+//   error: unexpected character `@`
+//    --> 1:11
+//     |
+//   1 | let x = 1 @ 2;
+//     |           ^
+pub fn render(source: &str, line: usize, column: usize, message: &str) -> String {
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+
+    // Tabs are kept so the caret lines up however wide they are shown
+    let padding: String = text
+        .chars()
+        .take(column - 1)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    return format!(
+        "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}^",
+        message, gutter, line, column, gutter, line, text, gutter, padding
+    );
+}
+
+// Checks `source` for a token the lexer could not read or a statement the
+// parser could not make sense of, returning it as a rendered error so it can
+// be reported before running anything.
+pub fn syntax_error(source: &str) -> Option<Box<dyn Object>> {
+    let mut lexer = Lexer::new(source.to_string());
+    loop {
+        let token = lexer.next_token();
+        if token.token_type == TokenType::EOF {
+            return parse_error(source);
+        }
+        if token.token_type != TokenType::ILLEGAL {
+            continue;
+        }
+
+        // Single characters are ones no token starts with, anything longer
+        // already describes the problem
+        let literal = token.literal.unwrap_or_default();
+        let message = if literal.chars().count() == 1 {
+            format!("unexpected character `{}`", literal)
+        } else {
+            literal
+        };
+        let (line, column) = line_column(source, token.span.0);
+        return Some(Box::new(Error {
+            kind: ErrorKind::SyntaxError,
+            data: None,
+            message: render(source, line, column, &message),
+        }));
+    }
+}
+
+fn parse_error(source: &str) -> Option<Box<dyn Object>> {
+    let parsed = Parser::new(Lexer::new(source.to_string())).try_parse();
+    if parsed.is_ok() {
+        return None;
+    }
+
+    let (message, offset) = parsed.err().unwrap();
+    let (line, column) = line_column(source, offset);
+    return Some(Box::new(Error {
+        kind: ErrorKind::SyntaxError,
+        data: None,
+        message: render(source, line, column, &message),
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "let a = 1;\nlet x = 1 @ 2;\n";
+        assert_eq!(line_column(source, 21), (2, 11));
+        assert_eq!(
+            render(source, 2, 11, "unexpected character `@`"),
+            "error: unexpected character `@`\n --> 2:11\n  |\n2 | let x = 1 @ 2;\n  |           ^"
+        );
+
+        let source = "\tlet é = 1;";
+        assert_eq!(line_column(source, 5), (1, 6));
+        assert_eq!(
+            render(source, 1, 6, "oops"),
+            "error: oops\n --> 1:6\n  |\n1 | \tlet é = 1;\n  | \t    ^"
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        assert!(syntax_error("let x = [1, 2];\nx[0]").is_none());
+
+        let error = syntax_error("let x = 5;\nlet y = x # 2;").unwrap();
+        assert_eq!(
+            error.inspect(),
+            "error: unexpected character `#`\n --> 2:11\n  |\n2 | let y = x # 2;\n  |           ^"
+        );
+        assert_eq!(
+            error.downcast_ref::<Error>().unwrap().kind,
+            ErrorKind::SyntaxError
+        );
+
+        let error = syntax_error("puts(\"unterminated)").unwrap();
+        assert!(error
+            .inspect()
+            .starts_with("error: String is not terminated with double quotes\n --> 1:6\n"));
+
+        // Statements the parser rejects are reported at the token it stopped on
+        assert_eq!(
+            syntax_error("let x = 1;\nlet = 5").unwrap().inspect(),
+            concat!(
+                "error: Expected next token to be IDENT, got ASSIGN instead\n",
+                " --> 2:5\n  |\n2 | let = 5\n  |     ^"
+            )
+        );
+        assert!(syntax_error("let h = {1: }")
+            .unwrap()
+            .inspect()
+            .starts_with("error: unexpected `}`\n --> 1:13\n"));
+        assert_eq!(
+            syntax_error("let n = 99999999999999999999;")
+                .unwrap()
+                .inspect(),
+            concat!(
+                "error: integer literal out of range: 99999999999999999999\n",
+                " --> 1:9\n  |\n1 | let n = 99999999999999999999;\n  |         ^"
+            )
+        );
+    }
+}
@@ -1,17 +1,426 @@
-use crate::types::{Error, Object};
-use std::collections::HashMap;
+use crate::builtins::get_builtin;
+use crate::program::{json_string, ArithmeticMode};
+use crate::types::{Array, Boolean, Error, ErrorKind, Hash, Integer, Null, Object, StringLiteral};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// A plain copy of a value without any functions in it, so it can be kept
+// outside the interpreter and turned back into an object later.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerializedValue {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Null,
+    Array(Vec<SerializedValue>),
+    Hash(Vec<(SerializedValue, SerializedValue)>),
+}
+
+impl SerializedValue {
+    // None for functions, builtins and errors, or collections holding them.
+    pub fn from_object(object: &dyn Object) -> Option<SerializedValue> {
+        let integer = object.downcast_ref::<Integer>();
+        if integer.is_some() {
+            return Some(SerializedValue::Integer(integer.unwrap().value));
+        }
+        let boolean = object.downcast_ref::<Boolean>();
+        if boolean.is_some() {
+            return Some(SerializedValue::Boolean(boolean.unwrap().value));
+        }
+        let string = object.downcast_ref::<StringLiteral>();
+        if string.is_some() {
+            return Some(SerializedValue::String(string.unwrap().value.clone()));
+        }
+        if object.downcast_ref::<Null>().is_some() {
+            return Some(SerializedValue::Null);
+        }
+
+        let array = object.downcast_ref::<Array>();
+        if array.is_some() {
+            let mut elements = vec![];
+            for element in &array.unwrap().elements {
+                elements.push(SerializedValue::from_object(element.as_ref())?);
+            }
+            return Some(SerializedValue::Array(elements));
+        }
+
+        let hash = object.downcast_ref::<Hash>();
+        if hash.is_some() {
+            let mut pairs = vec![];
+            for (key, value) in &hash.unwrap().pairs {
+                let key = SerializedValue::from_object(key.as_ref());
+                let value = SerializedValue::from_object(value.as_ref());
+                if key.is_none() || value.is_none() {
+                    return None;
+                }
+                pairs.push((key.unwrap(), value.unwrap()));
+            }
+            return Some(SerializedValue::Hash(pairs));
+        }
+        return None;
+    }
+
+    pub fn to_object(&self) -> Box<dyn Object> {
+        match self {
+            SerializedValue::Integer(value) => return Box::new(Integer { value: *value }),
+            SerializedValue::Boolean(value) => return Box::new(Boolean { value: *value }),
+            SerializedValue::String(value) => {
+                return Box::new(StringLiteral {
+                    value: value.clone(),
+                });
+            }
+            SerializedValue::Null => return Box::new(Null {}),
+            SerializedValue::Array(elements) => {
+                return Box::new(Array {
+                    elements: elements.iter().map(|x| x.to_object()).collect(),
+                });
+            }
+            SerializedValue::Hash(pairs) => {
+                let mut hash = Hash::new();
+                for (key, value) in pairs {
+                    hash.insert(key.to_object(), value.to_object());
+                }
+                return Box::new(hash);
+            }
+        }
+    }
+
+    // Hashes become `{"hash": [[key, value], ...]}`, since their keys needn't
+    // be strings.
+    pub fn to_json(&self) -> String {
+        match self {
+            SerializedValue::Integer(value) => return value.to_string(),
+            SerializedValue::Boolean(value) => return value.to_string(),
+            SerializedValue::String(value) => return json_string(value),
+            SerializedValue::Null => return "null".to_string(),
+            SerializedValue::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|x| x.to_json()).collect();
+                return format!("[{}]", elements.join(","));
+            }
+            SerializedValue::Hash(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("[{},{}]", key.to_json(), value.to_json()))
+                    .collect();
+                return format!("{{\"hash\":[{}]}}", pairs.join(","));
+            }
+        }
+    }
+
+    fn from_json(json: Json) -> Result<SerializedValue, String> {
+        match json {
+            Json::Null => return Ok(SerializedValue::Null),
+            Json::Boolean(value) => return Ok(SerializedValue::Boolean(value)),
+            Json::Integer(value) => return Ok(SerializedValue::Integer(value)),
+            Json::String(value) => return Ok(SerializedValue::String(value)),
+            Json::Array(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(SerializedValue::from_json(element)?);
+                }
+                return Ok(SerializedValue::Array(values));
+            }
+            Json::Object(mut fields) => {
+                if fields.len() != 1 || fields[0].0 != "hash" {
+                    return Err("expected an object of the form {\"hash\": [...]}".to_string());
+                }
+                let entries = match fields.remove(0).1 {
+                    Json::Array(entries) => entries,
+                    _ => return Err("expected a list of hash pairs".to_string()),
+                };
+                let mut pairs = vec![];
+                for entry in entries {
+                    let pair = match entry {
+                        Json::Array(pair) if pair.len() == 2 => pair,
+                        _ => return Err("expected a [key, value] pair".to_string()),
+                    };
+                    let mut pair = pair.into_iter();
+                    let key = SerializedValue::from_json(pair.next().unwrap())?;
+                    let value = SerializedValue::from_json(pair.next().unwrap())?;
+                    pairs.push((key, value));
+                }
+                return Ok(SerializedValue::Hash(pairs));
+            }
+        }
+    }
+}
+
+// The serializable part of an environment: its plain values by name, and
+// which of those names were bound with `const`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub values: BTreeMap<String, SerializedValue>,
+    pub constants: BTreeSet<String>,
+}
+
+impl Snapshot {
+    // Text for saving to a file, e.g.
+    // `{"values":{"n":1,"xs":[true,null]},"constants":["n"]}`.
+    pub fn to_json(&self) -> String {
+        let values: Vec<String> = self
+            .values
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json_string(name), value.to_json()))
+            .collect();
+        let constants: Vec<String> = self.constants.iter().map(|x| json_string(x)).collect();
+        return format!(
+            "{{\"values\":{{{}}},\"constants\":[{}]}}",
+            values.join(","),
+            constants.join(",")
+        );
+    }
+
+    pub fn from_json(text: &str) -> Result<Snapshot, String> {
+        let mut reader = JsonReader {
+            chars: text.chars().collect(),
+            idx: 0,
+        };
+        let json = reader.read_value()?;
+        reader.skip_whitespace();
+        if reader.idx < reader.chars.len() {
+            return Err(format!("unexpected trailing text at {}", reader.idx));
+        }
+
+        let fields = match json {
+            Json::Object(fields) => fields,
+            _ => return Err("expected a snapshot object".to_string()),
+        };
+        let mut snapshot = Snapshot {
+            values: BTreeMap::new(),
+            constants: BTreeSet::new(),
+        };
+        for (field, json) in fields {
+            match (field.as_str(), json) {
+                ("values", Json::Object(values)) => {
+                    for (name, value) in values {
+                        snapshot
+                            .values
+                            .insert(name, SerializedValue::from_json(value)?);
+                    }
+                }
+                ("constants", Json::Array(names)) => {
+                    for name in names {
+                        match name {
+                            Json::String(name) => snapshot.constants.insert(name),
+                            _ => return Err("expected constant names to be strings".to_string()),
+                        };
+                    }
+                }
+                (field, _) => return Err(format!("unexpected snapshot field: {}", field)),
+            }
+        }
+        return Ok(snapshot);
+    }
+}
+
+// Parsed JSON, before it is checked against the snapshot's shape. Numbers are
+// only ever integers.
+enum Json {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+struct JsonReader {
+    chars: Vec<char>,
+    idx: usize,
+}
+
+impl JsonReader {
+    fn skip_whitespace(&mut self) {
+        while self.idx < self.chars.len() && self.chars[self.idx].is_whitespace() {
+            self.idx += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        return self.chars.get(self.idx).copied();
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() != Some(expected) {
+            return Err(format!("expected `{}` at {}", expected, self.idx));
+        }
+        self.idx += 1;
+        return Ok(());
+    }
+
+    fn read_word(&mut self, word: &str, json: Json) -> Result<Json, String> {
+        let end = self.idx + word.chars().count();
+        if end > self.chars.len() || self.chars[self.idx..end].iter().collect::<String>() != word {
+            return Err(format!("unexpected character at {}", self.idx));
+        }
+        self.idx = end;
+        return Ok(json);
+    }
+
+    fn read_value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('n') => return self.read_word("null", Json::Null),
+            Some('t') => return self.read_word("true", Json::Boolean(true)),
+            Some('f') => return self.read_word("false", Json::Boolean(false)),
+            Some('"') => return Ok(Json::String(self.read_string()?)),
+            Some('[') => {
+                let mut elements = vec![];
+                self.idx += 1;
+                while self.peek() != Some(']') {
+                    if elements.len() > 0 {
+                        self.expect(',')?;
+                    }
+                    elements.push(self.read_value()?);
+                }
+                self.idx += 1;
+                return Ok(Json::Array(elements));
+            }
+            Some('{') => {
+                let mut fields = vec![];
+                self.idx += 1;
+                while self.peek() != Some('}') {
+                    if fields.len() > 0 {
+                        self.expect(',')?;
+                    }
+                    if self.peek() != Some('"') {
+                        return Err(format!("expected a field name at {}", self.idx));
+                    }
+                    let name = self.read_string()?;
+                    self.expect(':')?;
+                    fields.push((name, self.read_value()?));
+                }
+                self.idx += 1;
+                return Ok(Json::Object(fields));
+            }
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => {
+                let start = self.idx;
+                self.idx += 1;
+                // Fractions and exponents are read too, only to be rejected
+                while self.idx < self.chars.len()
+                    && (self.chars[self.idx].is_ascii_digit()
+                        || "+-.eE".contains(self.chars[self.idx]))
+                {
+                    self.idx += 1;
+                }
+                let number: String = self.chars[start..self.idx].iter().collect();
+                return number
+                    .parse::<i64>()
+                    .map(Json::Integer)
+                    .map_err(|_| format!("invalid integer: {}", number));
+            }
+            Some(_) => return Err(format!("unexpected character at {}", self.idx)),
+            None => return Err("unexpected end of input".to_string()),
+        }
+    }
+
+    // Reads a quoted string, starting at its opening quote.
+    fn read_string(&mut self) -> Result<String, String> {
+        self.idx += 1;
+        let mut string = String::new();
+        loop {
+            if self.idx >= self.chars.len() {
+                return Err("string is not terminated".to_string());
+            }
+            let ch = self.chars[self.idx];
+            self.idx += 1;
+            match ch {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = self.chars.get(self.idx).copied();
+                    self.idx += 1;
+                    match escaped {
+                        Some('"') => string.push('"'),
+                        Some('\\') => string.push('\\'),
+                        Some('/') => string.push('/'),
+                        Some('b') => string.push('\u{8}'),
+                        Some('f') => string.push('\u{c}'),
+                        Some('n') => string.push('\n'),
+                        Some('r') => string.push('\r'),
+                        Some('t') => string.push('\t'),
+                        Some('u') if self.idx + 4 <= self.chars.len() => {
+                            let hex: String = self.chars[self.idx..self.idx + 4].iter().collect();
+                            self.idx += 4;
+                            let decoded =
+                                u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                            if decoded.is_none() {
+                                return Err(format!("invalid escape: \\u{}", hex));
+                            }
+                            string.push(decoded.unwrap());
+                        }
+                        _ => return Err(format!("invalid escape at {}", self.idx - 1)),
+                    }
+                }
+                ch => string.push(ch),
+            }
+        }
+    }
+}
+
+// How many elements or pairs an array or hash may hold unless configured
+// otherwise.
+pub const DEFAULT_MAX_COLLECTION_SIZE: usize = 10_000_000;
+
+// Unseeded environments start from the clock, so runs differ unless a seed is
+// set.
+fn clock_seed() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+}
 
 pub struct Environment {
     pub store: HashMap<String, Box<dyn Object>>,
+    // Names bound with `const`, which can't be bound again
+    pub constants: HashSet<String>,
+    pub deadline: Option<Instant>,
+    pub arithmetic_mode: ArithmeticMode,
+    // Reading a missing name gives null rather than an error
+    pub undefined_is_null: bool,
+    // Largest array or hash a program may build
+    pub max_collection_size: usize,
+    // Files currently being imported, innermost last, to catch cycles
+    pub imports: Vec<String>,
+    // Evaluations per node type when profiling, shared with every copy so
+    // calls are counted too
+    pub profile: Option<Rc<RefCell<HashMap<&'static str, usize>>>>,
+    // State of the generator behind `random`, shared with every copy so
+    // calls keep advancing the same sequence
+    pub random_state: Rc<Cell<u64>>,
+    // Set while a return inside a loop is leaving the rest of the function
+    pub returning: bool,
 }
 
 impl Environment {
     pub fn new() -> Environment {
         return Environment {
             store: HashMap::new(),
+            constants: HashSet::new(),
+            deadline: None,
+            arithmetic_mode: ArithmeticMode::Checked,
+            undefined_is_null: false,
+            max_collection_size: DEFAULT_MAX_COLLECTION_SIZE,
+            imports: vec![],
+            profile: None,
+            random_state: Rc::new(Cell::new(clock_seed())),
+            returning: false,
         };
     }
 
+    // The next number from the `random` generator (splitmix64), the same
+    // sequence every time for the same seed.
+    pub fn next_random(&self) -> u64 {
+        let state = self.random_state.get().wrapping_add(0x9e3779b97f4a7c15);
+        self.random_state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        return z ^ (z >> 31);
+    }
+
     pub fn update(&mut self, key: String, value: Box<dyn Object>) {
         self.store.insert(key, value);
     }
@@ -27,7 +436,17 @@ impl Environment {
     pub fn get(&self, key: &str) -> Box<dyn Object> {
         let obj = self.store.get(key);
         if obj.is_none() {
+            let builtin = get_builtin(key);
+            if builtin.is_some() {
+                return builtin.unwrap();
+            }
+            if self.undefined_is_null {
+                return Box::new(Null {});
+            }
+
             return Box::new(Error {
+                kind: ErrorKind::UndefinedIdentifier,
+                data: None,
                 message: format!("unknown identifier: {}", key),
             });
         }
@@ -39,6 +458,137 @@ impl Environment {
         for (k, v) in &self.store {
             new_map.insert(k.clone(), v.get_box());
         }
-        return Environment { store: new_map };
+        return Environment {
+            store: new_map,
+            constants: self.constants.clone(),
+            deadline: self.deadline,
+            arithmetic_mode: self.arithmetic_mode,
+            undefined_is_null: self.undefined_is_null,
+            max_collection_size: self.max_collection_size,
+            imports: self.imports.clone(),
+            profile: self.profile.clone(),
+            random_state: self.random_state.clone(),
+            returning: false,
+        };
+    }
+
+    // Bindings whose values can't be serialized, like functions, are left out.
+    pub fn to_serializable(&self) -> Snapshot {
+        let mut values = BTreeMap::new();
+        for (key, value) in &self.store {
+            let serialized = SerializedValue::from_object(value.as_ref());
+            if serialized.is_some() {
+                values.insert(key.clone(), serialized.unwrap());
+            }
+        }
+        let constants = self
+            .constants
+            .iter()
+            .filter(|name| values.contains_key(*name))
+            .cloned()
+            .collect();
+        return Snapshot { values, constants };
+    }
+
+    pub fn from_serializable(snapshot: &Snapshot) -> Environment {
+        let mut env = Environment::new();
+        for (key, value) in &snapshot.values {
+            env.update(key.clone(), value.to_object());
+        }
+        env.constants.extend(snapshot.constants.iter().cloned());
+        return env;
+    }
+
+    pub fn record_eval(&self, node_type: &'static str) {
+        if self.profile.is_some() {
+            let mut profile = self.profile.as_ref().unwrap().borrow_mut();
+            *profile.entry(node_type).or_insert(0) += 1;
+        }
+    }
+
+    // Returns an error once the evaluation deadline has passed.
+    pub fn check_deadline(&self) -> Option<Box<dyn Object>> {
+        if self.deadline.is_some() && Instant::now() >= self.deadline.unwrap() {
+            return Some(Box::new(Error {
+                kind: ErrorKind::Timeout,
+                data: None,
+                message: "evaluation timed out".to_string(),
+            }));
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+
+    #[test]
+    fn test_serializable_round_trip() {
+        let mut session = Session::new();
+        session.eval(
+            "let n = -5; let ok = true; let s = \"a \\\"b\\\"\"; let nothing = if (false) { 1 };
+            let xs = [1, [\"two\"], {}]; let h = {\"k\": [1, 2], 3: false};
+            let f = fn(x) { x }; let fs = [1, f]; const limit = 3; const g = f;",
+        );
+
+        let snapshot = session.program.environment.to_serializable();
+        assert_eq!(
+            snapshot.values.keys().collect::<Vec<&String>>(),
+            vec!["h", "limit", "n", "nothing", "ok", "s", "xs"]
+        );
+        assert_eq!(snapshot.values["n"], SerializedValue::Integer(-5));
+        assert_eq!(snapshot.values["nothing"], SerializedValue::Null);
+        assert_eq!(
+            snapshot.constants.iter().collect::<Vec<&String>>(),
+            vec!["limit"]
+        );
+
+        let text = snapshot.to_json();
+        assert_eq!(
+            text,
+            "{\"values\":{\"h\":{\"hash\":[[\"k\",[1,2]],[3,false]]},\"limit\":3,\"n\":-5,\
+            \"nothing\":null,\"ok\":true,\"s\":\"a \\\"b\\\"\",\"xs\":[1,[\"two\"],{\"hash\":[]}]},\
+            \"constants\":[\"limit\"]}"
+        );
+        let reloaded = Snapshot::from_json(&text).unwrap();
+        assert_eq!(reloaded, snapshot);
+
+        let env = Environment::from_serializable(&reloaded);
+        assert_eq!(env.to_serializable(), snapshot);
+        let values = snapshot.values;
+        for key in values.keys() {
+            assert_eq!(
+                env.get(key).inspect(),
+                session.program.environment.get(key).inspect()
+            );
+        }
+
+        let mut session = Session::new();
+        session.program.environment = env;
+        let result = session.eval("h[3] == false && h[\"k\"][1] + len(xs) + n == 0");
+        assert_eq!(result.unwrap().inspect(), "true");
+        let result = session.eval("let limit = 4;");
+        assert_eq!(result.unwrap().inspect(), "cannot reassign constant: limit");
+
+        let bad_inputs = vec![
+            ("", "unexpected end of input"),
+            ("[]", "expected a snapshot object"),
+            ("{\"values\":{\"x\":1.5}}", "invalid integer: 1.5"),
+            (
+                "{\"values\":{\"x\":{\"k\":1}}}",
+                "expected an object of the form {\"hash\": [...]}",
+            ),
+            ("{\"values\":{}} x", "unexpected trailing text at 14"),
+        ];
+        for bad_input in bad_inputs {
+            assert_eq!(Snapshot::from_json(bad_input.0).err().unwrap(), bad_input.1);
+        }
+        let text = "{ \"values\": { \"s\": \"\\u00e9\\n\" }, \"constants\": [] }";
+        assert_eq!(
+            Snapshot::from_json(text).unwrap().values["s"],
+            SerializedValue::String("é\n".to_string())
+        );
     }
 }
@@ -1,39 +1,295 @@
 use crate::token::{Token, TokenType};
+use std::collections::VecDeque;
+
+pub const DEFAULT_MAX_INPUT_LEN: usize = 10_000_000;
+pub const DEFAULT_MAX_TOKENS: usize = 1_000_000;
+
+// Identifiers start with a letter or an underscore, so `_` alone is a valid
+// name, and may then contain digits.
+fn is_identifier_start(ch: char) -> bool {
+    return ch.is_alphabetic() || ch == '_';
+}
+
+fn is_identifier_char(ch: char) -> bool {
+    return is_identifier_start(ch) || ch.is_numeric();
+}
+
+// The token for `ident` when it is a reserved word.
+pub fn lookup_keyword(ident: &str) -> Option<Token> {
+    match ident {
+        "let" => return Some(Token::new(TokenType::LET, Some("let"))),
+        "const" => return Some(Token::new(TokenType::CONST, Some("const"))),
+        "fn" => return Some(Token::new(TokenType::FUNCTION, Some("fn"))),
+        "if" => return Some(Token::new(TokenType::IF, Some("if"))),
+        "else" => return Some(Token::new(TokenType::ELSE, Some("else"))),
+        "then" => return Some(Token::new(TokenType::THEN, Some("then"))),
+        "return" => return Some(Token::new(TokenType::RETURN, Some("return"))),
+        "import" => return Some(Token::new(TokenType::IMPORT, Some("import"))),
+        "in" => return Some(Token::new(TokenType::IN, Some("in"))),
+        "while" => return Some(Token::new(TokenType::WHILE, Some("while"))),
+        "do" => return Some(Token::new(TokenType::DO, Some("do"))),
+        "try" => return Some(Token::new(TokenType::TRY, Some("try"))),
+        "catch" => return Some(Token::new(TokenType::CATCH, Some("catch"))),
+        "throw" => return Some(Token::new(TokenType::THROW, Some("throw"))),
+        "match" => return Some(Token::new(TokenType::MATCH, Some("match"))),
+        "true" => return Some(Token::new(TokenType::TRUE, Some("true"))),
+        "false" => return Some(Token::new(TokenType::FALSE, Some("false"))),
+        _ => return None,
+    }
+}
+
+// A piece of a template string: text to keep as it is, or the source of an
+// expression from inside `${...}`.
+#[derive(Debug, PartialEq)]
+pub enum TemplateChunk {
+    Text(String),
+    Source(String),
+}
+
+// Splits the literal of a TEMPLATE token into its chunks. Escapes in the text
+// (\` \$ \\ \n \t) are resolved, expression sources are left as written.
+pub fn split_template(content: &str) -> Vec<TemplateChunk> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut chunks: Vec<TemplateChunk> = vec![];
+    let mut text = String::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let ch = chars[idx];
+        if ch == '\\' && idx + 1 < chars.len() {
+            match chars[idx + 1] {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                '`' | '$' | '\\' => text.push(chars[idx + 1]),
+                other => {
+                    text.push('\\');
+                    text.push(other);
+                }
+            }
+            idx += 2;
+            continue;
+        }
+        if ch != '$' || idx + 1 >= chars.len() || chars[idx + 1] != '{' {
+            text.push(ch);
+            idx += 1;
+            continue;
+        }
+
+        // Find the brace closing the expression, skipping any quoted parts
+        if text.len() > 0 {
+            chunks.push(TemplateChunk::Text(text));
+            text = String::new();
+        }
+        let start = idx + 2;
+        let mut depth = 1;
+        idx = start;
+        while idx < chars.len() {
+            match chars[idx] {
+                quote @ ('"' | '`') => {
+                    idx += 1;
+                    while idx < chars.len() && chars[idx] != quote {
+                        if chars[idx] == '\\' {
+                            idx += 1;
+                        }
+                        idx += 1;
+                    }
+                }
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            idx += 1;
+        }
+        let end = idx.min(chars.len());
+        chunks.push(TemplateChunk::Source(chars[start..end].iter().collect()));
+        idx = end + 1;
+    }
+
+    if text.len() > 0 {
+        chunks.push(TemplateChunk::Text(text));
+    }
+    return chunks;
+}
+
+// Yields the characters of an owned string, so a `String` can be lexed
+// through the same lazy path as any other character source.
+struct StringChars {
+    input: String,
+    offset: usize,
+}
+
+impl Iterator for StringChars {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.input[self.offset..].chars().next();
+        if ch.is_some() {
+            self.offset += ch.unwrap().len_utf8();
+        }
+        return ch;
+    }
+}
 
-#[derive(Debug)]
 pub struct Lexer {
-    input: Vec<char>,
+    // Characters are pulled from `source` only when needed, and only a small
+    // window around the current position is kept, starting at `window_start`.
+    source: Box<dyn Iterator<Item = char>>,
+    window: VecDeque<char>,
+    // Byte offset in the input of each character in `window`
+    window_offsets: VecDeque<usize>,
+    window_start: usize,
+    bytes_read: usize,
+    max_input_len: usize,
     position: usize,
     read_position: usize,
     ch: Option<char>,
+    line: usize,
+    line_start: usize,
+    // Where the line before the current one started, for unread_char
+    previous_line_start: usize,
+    max_tokens: usize,
+    token_count: usize,
+    limit_error: Option<String>,
+    halted: bool,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Lexer {
+        return Lexer::with_limits(input, DEFAULT_MAX_INPUT_LEN, DEFAULT_MAX_TOKENS);
+    }
+
+    // Inputs longer than `max_input_len` bytes are rejected without being lexed,
+    // and lexing stops once more than `max_tokens` tokens have been produced.
+    // Either guard yields a single ILLEGAL token describing the limit, then EOF.
+    pub fn with_limits(input: String, max_input_len: usize, max_tokens: usize) -> Lexer {
+        if input.len() > max_input_len {
+            let mut lexer = Lexer::from_chars(std::iter::empty());
+            lexer.limit_error = Some(format!(
+                "Input of {} bytes exceeds the maximum length of {} bytes",
+                input.len(),
+                max_input_len
+            ));
+            return lexer;
+        }
+
+        let mut lexer = Lexer::from_chars(StringChars { input, offset: 0 });
+        lexer.max_input_len = max_input_len;
+        lexer.max_tokens = max_tokens;
+        return lexer;
+    }
+
+    // Lexes lazily from any source of characters, so large inputs never need
+    // to be held in memory. The length limit is enforced as input is read.
+    pub fn from_chars(source: impl Iterator<Item = char> + 'static) -> Lexer {
         return Lexer {
-            input: input.chars().collect(),
+            source: Box::new(source),
+            window: VecDeque::new(),
+            window_offsets: VecDeque::new(),
+            window_start: 0,
+            bytes_read: 0,
+            max_input_len: DEFAULT_MAX_INPUT_LEN,
             position: 0,
             read_position: 0,
             ch: Some(' '),
+            line: 1,
+            line_start: 0,
+            previous_line_start: 0,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            token_count: 0,
+            limit_error: None,
+            halted: false,
         };
     }
 
+    // The character at `idx` in the whole input, reading up to it if needed.
+    fn char_at(&mut self, idx: usize) -> Option<char> {
+        if idx < self.window_start {
+            return None;
+        }
+
+        while idx >= self.window_start + self.window.len() {
+            if self.limit_error.is_some() {
+                return None;
+            }
+            // Input past the limit is never read, it ends like the input did
+            let ch = self.source.next()?;
+            if self.bytes_read + ch.len_utf8() > self.max_input_len {
+                self.limit_error = Some(format!(
+                    "Input exceeds the maximum length of {} bytes",
+                    self.max_input_len
+                ));
+                return None;
+            }
+            self.window.push_back(ch);
+            self.window_offsets.push_back(self.bytes_read);
+            self.bytes_read += ch.len_utf8();
+        }
+        return Some(self.window[idx - self.window_start]);
+    }
+
+    // Byte offset of the character at `idx`, or the end of what has been read.
+    fn byte_offset(&self, idx: usize) -> usize {
+        if idx >= self.window_start && idx < self.window_start + self.window.len() {
+            return self.window_offsets[idx - self.window_start];
+        }
+        return self.bytes_read;
+    }
+
     pub fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = None;
-        } else {
-            self.ch = Some(self.input[self.read_position]);
+        self.ch = self.char_at(self.read_position);
+        if self.read_position > self.position && self.is_newline_at(self.position) {
+            self.line += 1;
+            self.previous_line_start = self.line_start;
+            self.line_start = self.read_position;
         }
         self.position = self.read_position;
         self.read_position += 1;
+
+        // Only the character before the current one is needed again
+        while self.window_start + 1 < self.position && self.window.len() > 0 {
+            self.window.pop_front();
+            self.window_offsets.pop_front();
+            self.window_start += 1;
+        }
     }
 
-    pub fn peek_char(&mut self) -> Option<char> {
-        if self.read_position >= self.input.len() {
-            return None;
-        } else {
-            return Some(self.input[self.read_position]);
+    fn unread_char(&mut self) {
+        // Step back over the character that terminated a span, without ever
+        // moving before the start of the input.
+        if self.position > 0 && self.is_newline_at(self.position - 1) {
+            self.line -= 1;
+            self.line_start = self.previous_line_start;
         }
+        self.position = self.position.saturating_sub(1);
+        self.read_position = self.read_position.saturating_sub(1);
+    }
+
+    // Only `\n` ends a line, so `\r\n` counts as a single line break and a
+    // stray `\r` is treated as ordinary whitespace.
+    fn is_newline_at(&self, idx: usize) -> bool {
+        return idx >= self.window_start
+            && idx < self.window_start + self.window.len()
+            && self.window[idx - self.window_start] == '\n';
+    }
+
+    // The 1-based line of the most recently read character.
+    pub fn line(&self) -> usize {
+        return self.line;
+    }
+
+    // The 1-based column of the most recently read character.
+    pub fn column(&self) -> usize {
+        return self.position - self.line_start + 1;
+    }
+
+    pub fn peek_char(&mut self) -> Option<char> {
+        return self.char_at(self.read_position);
+    }
+
+    fn peek_char_at(&mut self, offset: usize) -> Option<char> {
+        return self.char_at(self.read_position + offset);
     }
 
     fn match_char(&mut self) -> Option<Token> {
@@ -47,6 +303,9 @@ impl Lexer {
                 } else if peeked.unwrap() == '=' {
                     self.read_char();
                     Some(Token::new(TokenType::EQ, Some("==")))
+                } else if peeked.unwrap() == '>' {
+                    self.read_char();
+                    Some(Token::new(TokenType::ARROW, Some("=>")))
                 } else {
                     Some(Token::new(TokenType::ASSIGN, Some("=")))
                 }
@@ -64,12 +323,69 @@ impl Lexer {
                 }
             }
 
-            Some('+') => Some(Token::new(TokenType::PLUS, Some("+").as_deref())),
+            Some('|') => {
+                let peeked = self.peek_char();
+                if peeked.is_some() && peeked.unwrap() == '>' {
+                    self.read_char();
+                    Some(Token::new(TokenType::PIPE, Some("|>")))
+                } else if peeked.is_some() && peeked.unwrap() == '|' {
+                    self.read_char();
+                    Some(Token::new(TokenType::OR, Some("||")))
+                } else {
+                    None
+                }
+            }
+
+            Some('?') => {
+                let peeked = self.peek_char();
+                if peeked.is_some() && peeked.unwrap() == '?' {
+                    self.read_char();
+                    Some(Token::new(TokenType::COALESCE, Some("??")))
+                } else {
+                    None
+                }
+            }
+
+            Some('&') => {
+                let peeked = self.peek_char();
+                if peeked.is_some() && peeked.unwrap() == '&' {
+                    self.read_char();
+                    Some(Token::new(TokenType::AND, Some("&&")))
+                } else {
+                    None
+                }
+            }
+
+            Some('+') => Some(Token::new(TokenType::PLUS, Some("+"))),
             Some('/') => Some(Token::new(TokenType::SLASH, Some("/"))),
-            Some('*') => Some(Token::new(TokenType::ASTERISK, Some("*"))),
+            Some('*') => {
+                let peeked = self.peek_char();
+                if peeked.is_some() && peeked.unwrap() == '*' {
+                    self.read_char();
+                    Some(Token::new(TokenType::POWER, Some("**")))
+                } else {
+                    Some(Token::new(TokenType::ASTERISK, Some("*")))
+                }
+            }
             Some('-') => Some(Token::new(TokenType::MINUS, Some("-"))),
-            Some('>') => Some(Token::new(TokenType::GT, Some(">"))),
-            Some('<') => Some(Token::new(TokenType::LT, Some("<"))),
+            Some('>') => {
+                let peeked = self.peek_char();
+                if peeked.is_some() && peeked.unwrap() == '=' {
+                    self.read_char();
+                    Some(Token::new(TokenType::GTE, Some(">=")))
+                } else {
+                    Some(Token::new(TokenType::GT, Some(">")))
+                }
+            }
+            Some('<') => {
+                let peeked = self.peek_char();
+                if peeked.is_some() && peeked.unwrap() == '=' {
+                    self.read_char();
+                    Some(Token::new(TokenType::LTE, Some("<=")))
+                } else {
+                    Some(Token::new(TokenType::LT, Some("<")))
+                }
+            }
 
             // Groupings
             Some('(') => Some(Token::new(
@@ -89,12 +405,39 @@ impl Lexer {
                 Some(self.ch.unwrap().to_string()).as_deref(),
             )),
 
+            Some('[') => Some(Token::new(
+                TokenType::LBRACKET,
+                Some(self.ch.unwrap().to_string()).as_deref(),
+            )),
+            Some(']') => Some(Token::new(
+                TokenType::RBRACKET,
+                Some(self.ch.unwrap().to_string()).as_deref(),
+            )),
+
             // Flow
             Some(',') => Some(Token::new(
                 TokenType::COMMA,
                 Some(self.ch.unwrap().to_string()).as_deref(),
             )),
 
+            Some('.') => {
+                if self.peek_char() == Some('.') && self.peek_char_at(1) == Some('.') {
+                    self.read_char();
+                    self.read_char();
+                    Some(Token::new(TokenType::ELLIPSIS, Some("...")))
+                } else if self.peek_char() == Some('.') {
+                    self.read_char();
+                    Some(Token::new(TokenType::DOTDOT, Some("..")))
+                } else {
+                    None
+                }
+            }
+
+            Some(':') => Some(Token::new(
+                TokenType::COLON,
+                Some(self.ch.unwrap().to_string()).as_deref(),
+            )),
+
             Some(';') => Some(Token::new(
                 TokenType::SEMICOLON,
                 Some(self.ch.unwrap().to_string()).as_deref(),
@@ -108,10 +451,10 @@ impl Lexer {
 
     fn match_alphabetic_span(&mut self) -> Option<Token> {
         let mut ident: Vec<char> = Vec::new();
-        if self.ch.is_none() {
+        if self.ch.is_none() || !is_identifier_start(self.ch.unwrap()) {
             return None;
         }
-        while self.ch.unwrap().is_alphabetic() & !self.ch.unwrap().is_whitespace() {
+        while is_identifier_char(self.ch.unwrap()) {
             ident.push(self.ch.unwrap());
             self.read_char();
 
@@ -126,83 +469,274 @@ impl Lexer {
             return None;
         }
 
-        let token = match &*ident_string {
-            "let" => Some(Token::new(TokenType::LET, Some("let"))),
-            "fn" => Some(Token::new(TokenType::FUNCTION, Some("fn"))),
-            "if" => Some(Token::new(TokenType::IF, Some("if"))),
-            "else" => Some(Token::new(TokenType::ELSE, Some("else"))),
-            "return" => Some(Token::new(TokenType::RETURN, Some("return"))),
-            "true" => Some(Token::new(TokenType::TRUE, Some("true"))),
-            "false" => Some(Token::new(TokenType::FALSE, Some("false"))),
-            _ => Some(Token::new(TokenType::IDENT, Some(&*ident_string))),
-        };
+        let mut token = lookup_keyword(&ident_string);
+        if token.is_none() {
+            token = Some(Token::new(TokenType::IDENT, Some(&*ident_string)));
+        }
 
-        self.read_position -= 1;
-        self.position -= 1;
+        self.unread_char();
 
         return token;
     }
 
     fn match_string_span(&mut self) -> Option<Token> {
-        let mut string_vec: Vec<char> = Vec::new();
-        if self.ch.is_none() {
+        if self.ch.is_none() || self.ch.unwrap() != '"' {
             return None;
         }
+        self.read_char();
 
-        if self.ch.unwrap() == '"' {
+        let mut string_vec: Vec<char> = Vec::new();
+        let mut error: Option<String> = None;
+        while self.ch.is_some() && self.ch.unwrap() != '"' {
+            if self.ch.unwrap() == '\\' {
+                match self.read_escape() {
+                    Ok(ch) => string_vec.push(ch),
+                    Err(msg) => {
+                        if error.is_none() {
+                            error = Some(msg);
+                        }
+                    }
+                }
+            } else {
+                string_vec.push(self.ch.unwrap());
+            }
             self.read_char();
+        }
 
-            if self.ch.is_none() {
-                return Some(Token::new(
-                    TokenType::ILLEGAL,
-                    Some("String is not terminated with double quotes"),
-                ));
-            }
+        if self.ch.is_none() {
+            return Some(Token::new(
+                TokenType::ILLEGAL,
+                Some("String is not terminated with double quotes"),
+            ));
+        }
 
-            let mut res: Option<Token> = None;
-            while (self.ch.unwrap().is_alphanumeric()
-                || self.ch.unwrap().is_whitespace()
-                || self.ch.unwrap().is_ascii_punctuation())
-            {
-                let next = self.peek_char();
-                string_vec.push(self.ch.unwrap());
+        if error.is_some() {
+            return Some(Token::new(TokenType::ILLEGAL, error.as_deref()));
+        }
 
-                if self.peek_char().is_some() {
-                    if self.peek_char().unwrap() == '"' {
-                        let string: String = string_vec.iter().collect();
+        let string: String = string_vec.iter().collect();
+        return Some(Token::new(TokenType::STRING, Some(&string)));
+    }
 
-                        if string.len() == 0 {
-                            res = None;
-                            break;
-                        }
-                        res = Some(Token::new(TokenType::STRING, Some(&string)));
-                        break;
+    // Reads the escape sequence starting at the current backslash, leaving the
+    // lexer on its last character.
+    fn read_escape(&mut self) -> Result<char, String> {
+        let invalid = |kind: &str| {
+            return format!("Invalid {} escape", kind);
+        };
+
+        self.read_char();
+        if self.ch.is_none() {
+            return Err(invalid("unterminated"));
+        }
+
+        match self.ch.unwrap() {
+            'n' => return Ok('\n'),
+            't' => return Ok('\t'),
+            'r' => return Ok('\r'),
+            '\\' => return Ok('\\'),
+            '"' => return Ok('"'),
+            'x' => {
+                let mut digits = String::new();
+                while digits.len() < 2 {
+                    if self.peek_char().is_none() || !self.peek_char().unwrap().is_ascii_hexdigit()
+                    {
+                        return Err(invalid("hex"));
                     }
+                    self.read_char();
+                    digits.push(self.ch.unwrap());
+                }
+                let value = u8::from_str_radix(&digits, 16).unwrap();
+                return Ok(value as char);
+            }
+            'u' => {
+                if self.peek_char().is_none() || self.peek_char().unwrap() != '{' {
+                    return Err(invalid("unicode"));
+                }
+                self.read_char();
+
+                let mut digits = String::new();
+                while digits.len() < 6
+                    && self.peek_char().is_some()
+                    && self.peek_char().unwrap().is_ascii_hexdigit()
+                {
+                    self.read_char();
+                    digits.push(self.ch.unwrap());
                 }
 
+                if digits.len() == 0
+                    || self.peek_char().is_none()
+                    || self.peek_char().unwrap() != '}'
+                {
+                    return Err(invalid("unicode"));
+                }
                 self.read_char();
 
-                if self.ch.is_none() {
-                    res = Some(Token::new(
-                        TokenType::ILLEGAL,
-                        Some("String is not terminated with double quotes"),
-                    ));
-                    break;
+                let value = u32::from_str_radix(&digits, 16).unwrap();
+                let ch = char::from_u32(value);
+                if ch.is_none() {
+                    return Err(format!("Unicode escape {} is out of range", digits));
                 }
+                return Ok(ch.unwrap());
             }
+            other => {
+                return Err(format!("Unknown escape sequence \\{}", other));
+            }
+        }
+    }
 
-            self.read_char();
-            return res;
-        } else {
+    // Raw strings (r"...") are scanned verbatim, so backslashes are kept as-is.
+    fn match_raw_string_span(&mut self) -> Option<Token> {
+        if self.ch.is_none() || self.ch.unwrap() != 'r' {
             return None;
         }
+
+        if self.peek_char().is_none() || self.peek_char().unwrap() != '"' {
+            return None;
+        }
+
+        self.read_char();
+        self.read_char();
+
+        let mut string_vec: Vec<char> = Vec::new();
+        while self.ch.is_some() && self.ch.unwrap() != '"' {
+            string_vec.push(self.ch.unwrap());
+            self.read_char();
+        }
+
+        if self.ch.is_none() {
+            return Some(Token::new(
+                TokenType::ILLEGAL,
+                Some("String is not terminated with double quotes"),
+            ));
+        }
+
+        let string: String = string_vec.iter().collect();
+        return Some(Token::new(TokenType::STRING, Some(&string)));
     }
 
-    fn match_numeric_span(&mut self) -> Option<Token> {
-        let mut numeric: Vec<char> = Vec::new();
+    // `name` in backticks is always an identifier, even when it is a keyword.
+    // Backticks around anything else, e.g. `Hello ${name}` or `Hello world`,
+    // make a template string whose raw contents become the token's literal.
+    fn match_backtick_span(&mut self) -> Option<Token> {
+        if self.ch.is_none() || self.ch.unwrap() != '`' {
+            return None;
+        }
+        self.read_char();
+
+        let mut ident: Vec<char> = Vec::new();
+        let mut template = false;
+        // How many braces are open inside the current `${...}`
+        let mut depth = 0;
+        while self.ch.is_some() && (depth > 0 || self.ch.unwrap() != '`') {
+            let ch = self.ch.unwrap();
+            ident.push(ch);
+            if ch == '\\' {
+                self.read_char();
+                if self.ch.is_some() {
+                    ident.push(self.ch.unwrap());
+                }
+            } else if depth == 0 && ch == '$' && self.peek_char() == Some('{') {
+                self.read_char();
+                ident.push('{');
+                template = true;
+                depth = 1;
+            } else if depth > 0 && (ch == '"' || ch == '`') {
+                // Strings and escaped names inside an expression may hold
+                // braces and backticks of their own
+                self.read_char();
+                while self.ch.is_some() && self.ch.unwrap() != ch {
+                    ident.push(self.ch.unwrap());
+                    if self.ch.unwrap() == '\\' {
+                        self.read_char();
+                        if self.ch.is_some() {
+                            ident.push(self.ch.unwrap());
+                        }
+                    }
+                    self.read_char();
+                }
+                if self.ch.is_some() {
+                    ident.push(ch);
+                }
+            } else if depth > 0 && ch == '{' {
+                depth += 1;
+            } else if depth > 0 && ch == '}' {
+                depth -= 1;
+            }
+            if self.ch.is_some() {
+                self.read_char();
+            }
+        }
+
+        let identifier = !template
+            && ident.len() > 0
+            && is_identifier_start(ident[0])
+            && ident.iter().all(|ch| is_identifier_char(*ch));
         if self.ch.is_none() {
+            let msg = if identifier {
+                "Identifier is not terminated with a backtick"
+            } else {
+                "Template string is not terminated with a backtick"
+            };
+            return Some(Token::new(TokenType::ILLEGAL, Some(msg)));
+        }
+
+        let content: String = ident.iter().collect();
+        if identifier {
+            return Some(Token::new(TokenType::IDENT, Some(&content)));
+        }
+        return Some(Token::new(TokenType::TEMPLATE, Some(&content)));
+    }
+
+    // Hex (0xFF) and binary (0b1010) literals, which may use `_` between
+    // digits, e.g. 0xFF_FF. The token holds the value in decimal.
+    fn match_radix_span(&mut self) -> Option<Token> {
+        if self.ch.is_none() || self.ch.unwrap() != '0' {
             return None;
         }
+        let radix = match self.peek_char() {
+            Some('x') => 16,
+            Some('b') => 2,
+            _ => return None,
+        };
+        self.read_char();
+        let mut literal = format!("0{}", self.ch.unwrap());
+
+        let mut digits = String::new();
+        while self.peek_char().is_some()
+            && (self.peek_char().unwrap().is_alphanumeric() || self.peek_char().unwrap() == '_')
+        {
+            self.read_char();
+            digits.push(self.ch.unwrap());
+        }
+        literal.push_str(&digits);
+
+        if digits.len() == 0 {
+            let msg = format!("Missing digits after {}", literal);
+            return Some(Token::new(TokenType::ILLEGAL, Some(&msg)));
+        }
+        if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+            let msg = format!("Misplaced digit separator in {}", literal);
+            return Some(Token::new(TokenType::ILLEGAL, Some(&msg)));
+        }
+        let digits = digits.replace('_', "");
+        if !digits.chars().all(|ch| ch.is_digit(radix)) {
+            let msg = format!("Invalid digit in {}", literal);
+            return Some(Token::new(TokenType::ILLEGAL, Some(&msg)));
+        }
+
+        match i64::from_str_radix(&digits, radix) {
+            Ok(value) => return Some(Token::new(TokenType::INT, Some(&value.to_string()))),
+            Err(_) => {
+                let msg = format!("Integer literal {} is out of range", literal);
+                return Some(Token::new(TokenType::ILLEGAL, Some(&msg)));
+            }
+        }
+    }
+
+    fn match_numeric_span(&mut self) -> Option<Token> {
+        let mut numeric: Vec<char> = Vec::new();
+        self.ch?;
         while self.ch.unwrap().is_numeric() {
             numeric.push(self.ch.unwrap());
             self.read_char();
@@ -217,13 +751,35 @@ impl Lexer {
             return None;
         }
 
-        self.read_position -= 1;
-        self.position -= 1;
+        self.unread_char();
 
         return Some(Token::new(TokenType::INT, Some(&*numeric_string)));
     }
 
     pub fn next_token(&mut self) -> Token {
+        if self.halted {
+            return Token::new(TokenType::EOF, None);
+        }
+
+        if self.limit_error.is_some() {
+            self.halted = true;
+            return Token::new(TokenType::ILLEGAL, self.limit_error.as_deref());
+        }
+
+        let token = self.lex_token();
+        if token.token_type != TokenType::EOF {
+            self.token_count += 1;
+            if self.token_count > self.max_tokens {
+                self.halted = true;
+                let msg = format!("Input exceeds the maximum of {} tokens", self.max_tokens);
+                return Token::new(TokenType::ILLEGAL, Some(&msg));
+            }
+        }
+
+        return token;
+    }
+
+    fn lex_token(&mut self) -> Token {
         // Next Token:
         // 1. Match Char
         //      - Identify if character is special character
@@ -233,13 +789,29 @@ impl Lexer {
         //      - Set character span to identifier
         // 4. If no match, set to ILLEGAL
 
+        self.read_char();
+        while self.ch.is_some() && self.ch.unwrap().is_whitespace() {
+            self.read_char();
+        }
+
+        // Every span ends on the token's last character, or at the end of the
+        // input when a string runs off it
+        let start = self.byte_offset(self.position);
+        let mut token = self.match_token();
+        let last = self.char_at(self.position);
+        let mut end = self.bytes_read;
+        if last.is_some() {
+            end = self.byte_offset(self.position) + last.unwrap().len_utf8();
+        }
+        token.span = (start, end.max(start));
+        return token;
+    }
+
+    fn match_token(&mut self) -> Token {
         let mut token: Option<Token>;
 
-        self.read_char();
         if self.ch.is_none() {
             return Token::new(TokenType::EOF, None);
-        } else if self.ch.unwrap().is_whitespace() {
-            return self.next_token();
         }
 
         token = self.match_char();
@@ -247,11 +819,26 @@ impl Lexer {
             return token.unwrap();
         }
 
+        token = self.match_raw_string_span();
+        if token.is_some() {
+            return token.unwrap();
+        }
+
+        token = self.match_backtick_span();
+        if token.is_some() {
+            return token.unwrap();
+        }
+
         token = self.match_alphabetic_span();
         if token.is_some() {
             return token.unwrap();
         }
 
+        token = self.match_radix_span();
+        if token.is_some() {
+            return token.unwrap();
+        }
+
         token = self.match_numeric_span();
         if token.is_some() {
             return token.unwrap();
@@ -262,7 +849,9 @@ impl Lexer {
             return token.unwrap();
         }
 
-        return Token::new(TokenType::ILLEGAL, None);
+        // Keep the offending character so errors can point at it
+        let illegal = self.ch.map(|ch| ch.to_string());
+        return Token::new(TokenType::ILLEGAL, illegal.as_deref());
     }
 }
 
@@ -504,6 +1093,424 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_leading_identifier_positions() {
+        let test_string = "x + 1";
+
+        let test_tokens = vec![
+            Token::new(TokenType::IDENT, Some("x")),
+            Token::new(TokenType::PLUS, Some("+")),
+            Token::new(TokenType::INT, Some("1")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+            assert!(lexer.position <= test_string.len());
+            assert!(lexer.read_position <= test_string.len() + 1);
+        }
+
+        let mut lexer = Lexer::new("x".to_string());
+        lexer.unread_char();
+        assert_eq!(lexer.position, 0);
+        assert_eq!(lexer.read_position, 0);
+        assert_eq!(lexer.next_token(), Token::new(TokenType::IDENT, Some("x")));
+    }
+
+    #[test]
+    fn test_pipe_lexer() {
+        let test_string = "5 |> double |> print";
+
+        let test_tokens = vec![
+            Token::new(TokenType::INT, Some("5")),
+            Token::new(TokenType::PIPE, Some("|>")),
+            Token::new(TokenType::IDENT, Some("double")),
+            Token::new(TokenType::PIPE, Some("|>")),
+            Token::new(TokenType::IDENT, Some("print")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+    }
+
+    #[test]
+    fn test_hash_lexer() {
+        let test_string = r#"{"a": 1, 2: b}"#;
+
+        let test_tokens = vec![
+            Token::new(TokenType::LBRACE, Some("{")),
+            Token::new(TokenType::STRING, Some("a")),
+            Token::new(TokenType::COLON, Some(":")),
+            Token::new(TokenType::INT, Some("1")),
+            Token::new(TokenType::COMMA, Some(",")),
+            Token::new(TokenType::INT, Some("2")),
+            Token::new(TokenType::COLON, Some(":")),
+            Token::new(TokenType::IDENT, Some("b")),
+            Token::new(TokenType::RBRACE, Some("}")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+    }
+
+    #[test]
+    fn test_index_lexer() {
+        let test_string = "args[0]";
+
+        let test_tokens = vec![
+            Token::new(TokenType::IDENT, Some("args")),
+            Token::new(TokenType::LBRACKET, Some("[")),
+            Token::new(TokenType::INT, Some("0")),
+            Token::new(TokenType::RBRACKET, Some("]")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+    }
+
+    #[test]
+    fn test_ellipsis_lexer() {
+        let test_string = "fn(first, ...rest)";
+
+        let test_tokens = vec![
+            Token::new(TokenType::FUNCTION, Some("fn")),
+            Token::new(TokenType::LPAREN, Some("(")),
+            Token::new(TokenType::IDENT, Some("first")),
+            Token::new(TokenType::COMMA, Some(",")),
+            Token::new(TokenType::ELLIPSIS, Some("...")),
+            Token::new(TokenType::IDENT, Some("rest")),
+            Token::new(TokenType::RPAREN, Some(")")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let résumé = \"a b\";\n  résumé == 10 @";
+        let mut lexer = Lexer::new(input.to_string());
+        let expected = vec![
+            "let", "résumé", "=", "\"a b\"", ";", "résumé", "==", "10", "@",
+        ];
+        for source in expected {
+            let token = lexer.next_token();
+            assert_eq!(&input[token.span.0..token.span.1], source);
+        }
+        let token = lexer.next_token();
+        assert_eq!(token.token_type, TokenType::EOF);
+        assert_eq!(token.span, (input.len(), input.len()));
+
+        let mut lexer = Lexer::new("x \"abc".to_string());
+        lexer.next_token();
+        assert_eq!(lexer.next_token().span, (2, 6));
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        let test_inputs = vec![
+            ("0xFF_FF", TokenType::INT, "65535"),
+            ("0xff", TokenType::INT, "255"),
+            ("0b1010_1010", TokenType::INT, "170"),
+            ("0b1_0_1", TokenType::INT, "5"),
+            (
+                "0x7FFF_FFFF_FFFF_FFFF",
+                TokenType::INT,
+                "9223372036854775807",
+            ),
+            (
+                "0x_FF",
+                TokenType::ILLEGAL,
+                "Misplaced digit separator in 0x_FF",
+            ),
+            (
+                "0xFF_",
+                TokenType::ILLEGAL,
+                "Misplaced digit separator in 0xFF_",
+            ),
+            (
+                "0b10__10",
+                TokenType::ILLEGAL,
+                "Misplaced digit separator in 0b10__10",
+            ),
+            ("0b102", TokenType::ILLEGAL, "Invalid digit in 0b102"),
+            ("0xFG", TokenType::ILLEGAL, "Invalid digit in 0xFG"),
+            ("0x", TokenType::ILLEGAL, "Missing digits after 0x"),
+            (
+                "0x8000_0000_0000_0000",
+                TokenType::ILLEGAL,
+                "Integer literal 0x8000_0000_0000_0000 is out of range",
+            ),
+        ];
+        for test_input in test_inputs {
+            let mut lexer = Lexer::new(test_input.0.to_string());
+            assert_eq!(
+                lexer.next_token(),
+                Token::new(test_input.1, Some(test_input.2))
+            );
+            assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+        }
+
+        let mut lexer = Lexer::new("0xFF_FF == 65535; 0 b".to_string());
+        let expected = vec![
+            Token::new(TokenType::INT, Some("65535")),
+            Token::new(TokenType::EQ, Some("==")),
+            Token::new(TokenType::INT, Some("65535")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(TokenType::INT, Some("0")),
+            Token::new(TokenType::IDENT, Some("b")),
+        ];
+        for token in expected {
+            assert_eq!(lexer.next_token(), token);
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        let input = "`hi ${name}` `${ {\"a\": \"}`\"}[\"a\"] }!` `if` `Hello world` `${x";
+        let mut lexer = Lexer::new(input.to_string());
+        let expected = vec![
+            Token::new(TokenType::TEMPLATE, Some("hi ${name}")),
+            Token::new(TokenType::TEMPLATE, Some("${ {\"a\": \"}`\"}[\"a\"] }!")),
+            Token::new(TokenType::IDENT, Some("if")),
+            Token::new(TokenType::TEMPLATE, Some("Hello world")),
+            Token::new(
+                TokenType::ILLEGAL,
+                Some("Template string is not terminated with a backtick"),
+            ),
+            Token::new(TokenType::EOF, None),
+        ];
+        for token in expected {
+            assert_eq!(lexer.next_token(), token);
+        }
+
+        assert_eq!(
+            split_template("a ${x + 1} b \\${c} \\` ${ {\"k\": \"}\"}[\"k\"] }"),
+            vec![
+                TemplateChunk::Text("a ".to_string()),
+                TemplateChunk::Source("x + 1".to_string()),
+                TemplateChunk::Text(" b ${c} ` ".to_string()),
+                TemplateChunk::Source(" {\"k\": \"}\"}[\"k\"] ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escaped_identifier() {
+        let mut lexer = Lexer::new("let `let` = `x1`; `if`+ `` `1a` `open".to_string());
+        let expected = vec![
+            Token::new(TokenType::LET, Some("let")),
+            Token::new(TokenType::IDENT, Some("let")),
+            Token::new(TokenType::ASSIGN, Some("=")),
+            Token::new(TokenType::IDENT, Some("x1")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(TokenType::IDENT, Some("if")),
+            Token::new(TokenType::PLUS, Some("+")),
+            // Anything that isn't a name is a template string
+            Token::new(TokenType::TEMPLATE, Some("")),
+            Token::new(TokenType::TEMPLATE, Some("1a")),
+            Token::new(
+                TokenType::ILLEGAL,
+                Some("Identifier is not terminated with a backtick"),
+            ),
+            Token::new(TokenType::EOF, None),
+        ];
+        for token in expected {
+            assert_eq!(lexer.next_token(), token);
+        }
+    }
+
+    #[test]
+    fn test_streaming_lexer() {
+        let input = "let add = fn(a, ...rest) {\n  a + rest[0] ** 2;\n};\nlet s = \"héllo\\n\";\nadd(1, 2) >= 5 |> `if`";
+        let mut buffered = Lexer::new(input.to_string());
+        let mut streamed = Lexer::from_chars(input.chars().collect::<Vec<char>>().into_iter());
+        loop {
+            let expected = buffered.next_token();
+            let token = streamed.next_token();
+            assert_eq!(token, expected);
+            assert_eq!(token.span, expected.span);
+            assert_eq!(
+                (streamed.line(), streamed.column()),
+                (buffered.line(), buffered.column())
+            );
+            if expected.token_type == TokenType::EOF {
+                break;
+            }
+        }
+
+        // Only a couple of characters are held on to, however long the input
+        let mut lexer = Lexer::from_chars(
+            "x + "
+                .repeat(1000)
+                .chars()
+                .collect::<Vec<char>>()
+                .into_iter(),
+        );
+        while lexer.next_token().token_type != TokenType::EOF {
+            assert!(lexer.window.len() <= 3);
+        }
+
+        let mut lexer = Lexer::from_chars(std::iter::repeat('1'));
+        lexer.max_input_len = 8;
+        assert_eq!(
+            lexer.next_token(),
+            Token::new(TokenType::INT, Some("11111111"))
+        );
+        let token = lexer.next_token();
+        assert_eq!(token.token_type, TokenType::ILLEGAL);
+        assert_eq!(
+            token.literal.unwrap(),
+            "Input exceeds the maximum length of 8 bytes"
+        );
+        assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+    }
+
+    #[test]
+    fn test_illegal_character() {
+        let mut lexer = Lexer::new("let x = @;".to_string());
+        let expected = vec![
+            Token::new(TokenType::LET, Some("let")),
+            Token::new(TokenType::IDENT, Some("x")),
+            Token::new(TokenType::ASSIGN, Some("=")),
+            Token::new(TokenType::ILLEGAL, Some("@")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(TokenType::EOF, None),
+        ];
+        for token in expected {
+            assert_eq!(lexer.next_token(), token);
+        }
+    }
+
+    #[test]
+    fn test_input_limits() {
+        let mut lexer = Lexer::with_limits("let x = 5;".to_string(), 5, DEFAULT_MAX_TOKENS);
+        let token = lexer.next_token();
+        assert_eq!(token.token_type, TokenType::ILLEGAL);
+        assert_eq!(
+            token.literal.unwrap(),
+            "Input of 10 bytes exceeds the maximum length of 5 bytes"
+        );
+        assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+
+        let mut lexer = Lexer::with_limits("1 + 2 + 3".to_string(), DEFAULT_MAX_INPUT_LEN, 3);
+        assert_eq!(lexer.next_token(), Token::new(TokenType::INT, Some("1")));
+        assert_eq!(lexer.next_token(), Token::new(TokenType::PLUS, Some("+")));
+        assert_eq!(lexer.next_token(), Token::new(TokenType::INT, Some("2")));
+        let token = lexer.next_token();
+        assert_eq!(token.token_type, TokenType::ILLEGAL);
+        assert_eq!(
+            token.literal.unwrap(),
+            "Input exceeds the maximum of 3 tokens"
+        );
+        assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+
+        let mut lexer = Lexer::with_limits("1 + 2".to_string(), 5, 3);
+        assert_eq!(lexer.next_token(), Token::new(TokenType::INT, Some("1")));
+        assert_eq!(lexer.next_token(), Token::new(TokenType::PLUS, Some("+")));
+        assert_eq!(lexer.next_token(), Token::new(TokenType::INT, Some("2")));
+        assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+    }
+
+    #[test]
+    fn test_crlf_lexer() {
+        let test_string = "let x = 5;\r\nlet y = 6;";
+
+        let test_tokens = vec![
+            (Token::new(TokenType::LET, Some("let")), 1, 3),
+            (Token::new(TokenType::IDENT, Some("x")), 1, 5),
+            (Token::new(TokenType::ASSIGN, Some("=")), 1, 7),
+            (Token::new(TokenType::INT, Some("5")), 1, 9),
+            (Token::new(TokenType::SEMICOLON, Some(";")), 1, 10),
+            (Token::new(TokenType::LET, Some("let")), 2, 3),
+            (Token::new(TokenType::IDENT, Some("y")), 2, 5),
+            (Token::new(TokenType::ASSIGN, Some("=")), 2, 7),
+            (Token::new(TokenType::INT, Some("6")), 2, 9),
+            (Token::new(TokenType::SEMICOLON, Some(";")), 2, 10),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token.0);
+            assert_eq!(lexer.line(), test_token.1);
+            assert_eq!(lexer.column(), test_token.2);
+        }
+        assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+        assert_eq!(lexer.line(), 2);
+
+        let mut lexer = Lexer::new("a\r\n\r\nb\nc".to_string());
+        lexer.next_token();
+        assert_eq!(lexer.line(), 1);
+        lexer.next_token();
+        assert_eq!(lexer.line(), 3);
+        lexer.next_token();
+        assert_eq!(lexer.line(), 4);
+        assert_eq!(lexer.column(), 1);
+    }
+
+    #[test]
+    fn test_identifier_lexer() {
+        let test_string = "foo_bar x1 counter2 _private snake_case_2 9lives";
+
+        let test_tokens = vec![
+            Token::new(TokenType::IDENT, Some("foo_bar")),
+            Token::new(TokenType::IDENT, Some("x1")),
+            Token::new(TokenType::IDENT, Some("counter2")),
+            Token::new(TokenType::IDENT, Some("_private")),
+            Token::new(TokenType::IDENT, Some("snake_case_2")),
+            Token::new(TokenType::INT, Some("9")),
+            Token::new(TokenType::IDENT, Some("lives")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+    }
+
+    #[test]
+    fn test_underscore_identifier_lexer() {
+        let test_string = "let _ = _x + __;";
+
+        let test_tokens = vec![
+            Token::new(TokenType::LET, Some("let")),
+            Token::new(TokenType::IDENT, Some("_")),
+            Token::new(TokenType::ASSIGN, Some("=")),
+            Token::new(TokenType::IDENT, Some("_x")),
+            Token::new(TokenType::PLUS, Some("+")),
+            Token::new(TokenType::IDENT, Some("__")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+    }
+
     #[test]
     fn test_string_lexer() {
         let test_string = r#"let x = "this is a test""#;
@@ -521,4 +1528,67 @@ mod tests {
             assert_eq!(token, test_token);
         }
     }
+
+    #[test]
+    fn test_raw_string_lexer() {
+        let test_string = r#"let p = r"C:\path\no\escapes"; r"\n"; r; r"unterminated"#;
+
+        let test_tokens = vec![
+            Token::new(TokenType::LET, Some("let")),
+            Token::new(TokenType::IDENT, Some("p")),
+            Token::new(TokenType::ASSIGN, Some("=")),
+            Token::new(TokenType::STRING, Some(r"C:\path\no\escapes")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(TokenType::STRING, Some(r"\n")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(TokenType::IDENT, Some("r")),
+            Token::new(TokenType::SEMICOLON, Some(";")),
+            Token::new(
+                TokenType::ILLEGAL,
+                Some("String is not terminated with double quotes"),
+            ),
+            Token::new(TokenType::EOF, None),
+        ];
+
+        let mut lexer = Lexer::new(test_string.to_string());
+        for test_token in test_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token, test_token);
+        }
+
+        let mut lexer = Lexer::new(r#"r"\n""#.to_string());
+        let token = lexer.next_token();
+        assert_eq!(token.literal.unwrap().chars().count(), 2);
+    }
+
+    #[test]
+    fn test_string_escape_lexer() {
+        let test_inputs = vec![
+            (r#""a\nb""#, TokenType::STRING, "a\nb"),
+            (r#""tab\there""#, TokenType::STRING, "tab\there"),
+            (r#""say \"hi\"""#, TokenType::STRING, "say \"hi\""),
+            (r#""back\\slash""#, TokenType::STRING, "back\\slash"),
+            (r#""\x41""#, TokenType::STRING, "A"),
+            (r#""\u{1F600}""#, TokenType::STRING, "\u{1F600}"),
+            (r#""\u{e9}t\u{E9}""#, TokenType::STRING, "été"),
+            (r#""""#, TokenType::STRING, ""),
+            (r#""\xZ1""#, TokenType::ILLEGAL, "Invalid hex escape"),
+            (
+                r#""ab\u{110000}""#,
+                TokenType::ILLEGAL,
+                "Unicode escape 110000 is out of range",
+            ),
+            (r#""\u{41""#, TokenType::ILLEGAL, "Invalid unicode escape"),
+            (r#""\q""#, TokenType::ILLEGAL, "Unknown escape sequence \\q"),
+        ];
+
+        for test_input in test_inputs {
+            let mut lexer = Lexer::new(test_input.0.to_string());
+            assert_eq!(
+                lexer.next_token(),
+                Token::new(test_input.1, Some(test_input.2))
+            );
+            assert_eq!(lexer.next_token(), Token::new(TokenType::EOF, None));
+        }
+    }
 }
@@ -1,9 +1,24 @@
 #![allow(dead_code)]
+// The crate's style: explicit returns, is_some/is_none checks followed by
+// unwrap, len() == 0 and upper-case token names
+#![allow(
+    clippy::len_zero,
+    clippy::needless_return,
+    clippy::new_without_default,
+    clippy::unnecessary_unwrap,
+    clippy::upper_case_acronyms
+)]
 pub mod ast;
+pub mod builtins;
+pub mod diagnostics;
 pub mod environment;
 pub mod lexer;
 pub mod program;
 pub mod repl;
+pub mod runner;
+pub mod session;
 pub mod statements;
 pub mod token;
 pub mod types;
+
+pub use runner::{check_string, eval_in};
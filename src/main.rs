@@ -1,6 +1,62 @@
 use blang::repl::REPL;
+use blang::runner::{run_file, run_files, run_reader};
+use blang::types::Object;
+use std::env;
+use std::io::{stdin, IsTerminal};
+use std::process::exit;
+
+// Prints a program's result, sending errors to stderr.
+fn report(result: Box<dyn Object>) {
+    if result.is_error() {
+        eprintln!("{}", result.inspect());
+    } else {
+        println!("{}", result.inspect());
+    }
+}
 
 fn main() {
-    let repl = REPL::new(">> ".to_string());
+    // `blang script.blang a b` runs the script with args bound to ["a", "b"]
+    let argv: Vec<String> = env::args().skip(1).collect();
+
+    // `blang --batch lib.blang main.blang` runs the scripts in order, sharing
+    // their definitions
+    if !argv.is_empty() && argv[0] == "--batch" {
+        match run_files(&argv[1..]) {
+            Ok(Some(result)) => report(result),
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!("{}", error);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if !argv.is_empty() {
+        match run_file(&argv[0], &argv[1..]) {
+            Ok(Some(result)) => report(result),
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!("failed to read {}: {}", argv[0], error);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    // Piped input is run as a single program, otherwise start the REPL
+    if !stdin().is_terminal() {
+        match run_reader(stdin()) {
+            Ok(Some(result)) => report(result),
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!("failed to read program: {}", error);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut repl = REPL::new(">> ".to_string());
     repl.run();
 }
@@ -1,12 +1,40 @@
-use crate::environment::Environment;
-use crate::statements::is_error;
-use crate::types::Object;
+use crate::builtins::get_builtin;
+use crate::environment::{Environment, DEFAULT_MAX_COLLECTION_SIZE};
+use crate::statements::{
+    BlockStatement, ExpressionStatement, FunctionLiteralExpression, GroupedExpression,
+    IdentifierExpression, IfExpression, ImportStatement, LetInExpression, LetStatement,
+    RestParameterExpression, TryExpression,
+};
+use crate::types::{is_error_option, Object};
 use downcast_rs::{impl_downcast, Downcast};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// How integer arithmetic behaves on overflow: an error by default, or
+// wrapping around when opted into.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ArithmeticMode {
+    Checked,
+    Wrapping,
+}
 
 pub struct Program {
     pub statements: Vec<Box<dyn ProgramNode>>,
     pub environment: Environment,
+    // Wall-clock limit for a single call to `eval`
+    pub timeout: Option<Duration>,
+    pub arithmetic_mode: ArithmeticMode,
+    // Read undefined identifiers as null instead of erroring
+    pub undefined_is_null: bool,
+    // Largest array or hash the program may build, for untrusted scripts
+    pub max_collection_size: usize,
+    // Count how many times each type of node is evaluated, see `profile`
+    pub profiling: bool,
     current_idx: usize,
+    // The statement the last `eval` stopped on with an error
+    failed_idx: Option<usize>,
 }
 
 impl Program {
@@ -14,14 +42,59 @@ impl Program {
         return Program {
             statements,
             environment: Environment::new(),
+            timeout: None,
+            arithmetic_mode: ArithmeticMode::Checked,
+            undefined_is_null: false,
+            max_collection_size: DEFAULT_MAX_COLLECTION_SIZE,
+            profiling: false,
             current_idx: 0,
+            failed_idx: None,
         };
     }
 
+    // Copies the parsed statements into a new program with an empty
+    // environment, so the same parse can be evaluated repeatedly.
+    pub fn clone_fresh(&self) -> Program {
+        let mut statements: Vec<Box<dyn ProgramNode>> = vec![];
+        for statement in &self.statements {
+            statements.push(statement.get_copy());
+        }
+        let mut program = Program::new(statements);
+        program.timeout = self.timeout;
+        program.arithmetic_mode = self.arithmetic_mode;
+        program.undefined_is_null = self.undefined_is_null;
+        program.max_collection_size = self.max_collection_size;
+        program.profiling = self.profiling;
+        return program;
+    }
+
     pub fn extend(&mut self, statements: Vec<Box<dyn ProgramNode>>) {
         self.statements.extend(statements);
     }
 
+    // Drops the most recent statement, leaving the environment as it is.
+    pub fn pop(&mut self) -> Option<Box<dyn ProgramNode>> {
+        let statement = self.statements.pop();
+        if self.current_idx > self.statements.len() {
+            self.current_idx = self.statements.len();
+        }
+        return statement;
+    }
+
+    // Restarts the sequence `random` draws from, so runs with the same seed
+    // see the same numbers.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.environment.random_state.set(seed);
+    }
+
+    // Drops the statements that have already been evaluated. Their bindings
+    // stay in the environment, so long-lived programs like the REPL's don't
+    // hold on to every statement ever entered.
+    pub fn discard_evaluated(&mut self) {
+        self.statements.drain(..self.current_idx);
+        self.current_idx = 0;
+    }
+
     fn total_statements(&self) -> usize {
         return self.statements.len();
     }
@@ -31,25 +104,70 @@ impl Program {
     }
 
     pub fn eval(&mut self) -> Option<Box<dyn Object>> {
+        let mut last: Option<Box<dyn Object>> = None;
+        self.run(|result| last = result);
+        return last;
+    }
+
+    // Evaluates like `eval`, but keeps the result of every statement run, in
+    // order, e.g. for showing the output of each cell of a notebook.
+    pub fn eval_collect(&mut self) -> Vec<Option<Box<dyn Object>>> {
+        let mut results: Vec<Option<Box<dyn Object>>> = vec![];
+        self.run(|result| results.push(result));
+        return results;
+    }
+
+    // Runs the statements not yet evaluated, handing each result to
+    // `on_result` as soon as the statement is done with it.
+    fn run(&mut self, mut on_result: impl FnMut(Option<Box<dyn Object>>)) {
+        self.failed_idx = None;
         if self.current_idx > self.total_statements() {
-            return None;
+            return;
         }
 
-        let mut result: Option<Box<dyn Object>> = None;
-        for idx in self.current_idx..self.total_statements() {
-            // Get Result
-            result = self.statements[idx].eval(&mut self.environment);
+        self.environment.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.environment.arithmetic_mode = self.arithmetic_mode;
+        self.environment.undefined_is_null = self.undefined_is_null;
+        self.environment.max_collection_size = self.max_collection_size;
+        if !self.profiling {
+            self.environment.profile = None;
+        } else if self.environment.profile.is_none() {
+            self.environment.profile = Some(Rc::new(RefCell::new(HashMap::new())));
+        }
 
-            if self.statements[idx].token_literal().unwrap() == "return" {
-                return result;
+        for idx in self.current_idx..self.total_statements() {
+            let timed_out = self.environment.check_deadline();
+            if timed_out.is_some() {
+                self.failed_idx = Some(idx);
+                on_result(timed_out);
+                return;
             }
 
-            if is_error(result.as_ref()) {
-                return result;
+            // Get Result
+            let result = self.statements[idx].eval(&mut self.environment);
+            if is_error_option(&result) {
+                self.failed_idx = Some(idx);
+            }
+            let stop = self.statements[idx].token_literal().unwrap() == "return"
+                || self.environment.returning
+                || self.failed_idx.is_some();
+            self.environment.returning = false;
+            if stop {
+                on_result(result);
+                return;
             }
 
             // Update environment if Needed
-            let env_update = self.statements[idx].update_env(&mut self.environment);
+            let env_update = match self.statements[idx].update_env(&mut self.environment) {
+                Ok(env_update) => env_update,
+                Err(error) => {
+                    // The binding failing is the statement's result
+                    self.failed_idx = Some(idx);
+                    on_result(Some(error));
+                    return;
+                }
+            };
+            on_result(result);
             if env_update.is_some() {
                 let unwrapped = env_update.unwrap();
                 for update in unwrapped {
@@ -60,33 +178,312 @@ impl Program {
             // Move Along
             self.current_idx += 1;
         }
+    }
+
+    // The parsed statements as a JSON array, see `ProgramNode::to_json`.
+    pub fn to_json(&self) -> String {
+        let statements: Vec<String> = self.statements.iter().map(|x| x.to_json()).collect();
+        return format!("[{}]", statements.join(","));
+    }
+
+    // How many times each type of node has been evaluated since profiling
+    // was turned on or the counts were last reset.
+    pub fn profile(&self) -> BTreeMap<String, usize> {
+        let mut summary = BTreeMap::new();
+        if self.environment.profile.is_some() {
+            for (node_type, count) in self.environment.profile.as_ref().unwrap().borrow().iter() {
+                summary.insert(node_type.to_string(), *count);
+            }
+        }
+        return summary;
+    }
+
+    pub fn reset_profile(&mut self) {
+        if self.environment.profile.is_some() {
+            self.environment
+                .profile
+                .as_ref()
+                .unwrap()
+                .borrow_mut()
+                .clear();
+        }
+    }
+
+    // Static checks over the parsed program, without evaluating anything.
+    // Warnings point at likely mistakes, errors at code that would fail.
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let mut warnings = unreachable_after_return(self.statements.iter().map(|x| x.as_ref()));
+        for statement in &self.statements {
+            warnings.extend(fold(
+                statement.as_ref(),
+                &|node, children: Vec<Vec<String>>| {
+                    let mut warnings: Vec<String> = children.into_iter().flatten().collect();
+                    let block = node.downcast_ref::<BlockStatement>();
+                    if block.is_some() {
+                        let statements = block.unwrap().statements.iter().map(|x| x.as_ref());
+                        warnings.extend(unreachable_after_return(statements));
+                    }
+                    return warnings;
+                },
+            ));
+        }
+
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        for name in self.undefined_identifiers() {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                message: format!("unknown identifier: {}", name),
+            });
+        }
+        for message in warnings {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                message,
+            });
+        }
+        return diagnostics;
+    }
+
+    // Scoping is dynamic, so a name only counts as undefined when nothing in
+    // the program, the environment or the builtins could ever bind it.
+    fn undefined_identifiers(&self) -> Vec<String> {
+        // Imported files aren't read without evaluating, so any name could
+        // come from one of them
+        let imports = |node: &dyn ProgramNode, children: Vec<bool>| {
+            return node.is::<ImportStatement>() || children.contains(&true);
+        };
+        if self
+            .statements
+            .iter()
+            .any(|statement| fold(statement.as_ref(), &imports))
+        {
+            return vec![];
+        }
+
+        let mut bound: Vec<String> = vec![];
+        let mut used: Vec<String> = vec![];
+        for statement in &self.statements {
+            let names = fold(statement.as_ref(), &bound_and_used_names);
+            bound.extend(names.0);
+            used.extend(names.1);
+        }
 
-        return result;
+        let mut undefined: Vec<String> = vec![];
+        for name in used {
+            if !bound.contains(&name)
+                && !undefined.contains(&name)
+                && !self.environment.has_key(&name)
+                && get_builtin(&name).is_none()
+            {
+                undefined.push(name);
+            }
+        }
+        return undefined;
     }
 
+    // Drops the statement the last `eval` failed on, without running it again.
     pub fn walk_back_error(&mut self) {
-        let result = self.statements[self.current_idx].eval(&mut self.environment);
-        if is_error(result.as_ref()) {
-            self.statements.remove(self.current_idx);
+        if self.failed_idx.is_some() {
+            self.statements.remove(self.failed_idx.unwrap());
+            self.failed_idx = None;
         }
     }
 }
 
+// Bindings a statement adds to the environment once it has been evaluated.
+pub type EnvUpdates = Option<Vec<(String, Box<dyn Object>)>>;
+
 pub trait ProgramNode: Downcast {
     fn to_string(&self) -> String;
     fn token_literal(&self) -> Option<String>;
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>>;
-    fn update_env(&self, env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>>;
+    fn update_env(&self, env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>>;
     fn get_copy(&self) -> Box<dyn ProgramNode>;
+
+    // The name of the node's type, e.g. `InfixExpression`, for profiling.
+    fn node_type(&self) -> &'static str {
+        return std::any::type_name::<Self>().rsplit("::").next().unwrap();
+    }
+
+    // The nodes directly beneath this one, in source order. Leaves have none.
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![];
+    }
+
+    // The node as JSON for external tools, e.g.
+    // `{"kind":"InfixExpression","operator":"+","left":...,"right":...}`.
+    // Nodes without their own fields list their token and children.
+    fn to_json(&self) -> String {
+        let children: Vec<String> = self.children().iter().map(|x| x.to_json()).collect();
+        return format!(
+            "{{\"kind\":\"{}\",\"token\":{},\"children\":[{}]}}",
+            self.node_type(),
+            json_string(&self.token_literal().unwrap_or_default()),
+            children.join(",")
+        );
+    }
 }
 
 impl_downcast!(ProgramNode);
 
+// Quotes `value` as a JSON string.
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+// Folds the tree bottom up: `f` gets each node along with the values already
+// folded from its children.
+pub fn fold<T>(node: &dyn ProgramNode, f: &impl Fn(&dyn ProgramNode, Vec<T>) -> T) -> T {
+    let folded = node
+        .children()
+        .into_iter()
+        .map(|child| fold(child, f))
+        .collect();
+    return f(node, folded);
+}
+
+// Whether two trees have the same shape, node types and values, however
+// they were spaced or parenthesized in the source.
+pub fn ast_equal(a: &dyn ProgramNode, b: &dyn ProgramNode) -> bool {
+    let a = ungrouped(a);
+    let b = ungrouped(b);
+    if a.node_type() != b.node_type() {
+        return false;
+    }
+
+    // Leaves are compared by their source, everything else by its token
+    // (the operator, keyword, ...) and children. An expression statement's
+    // token is just wherever the expression started.
+    let a_children = a.children();
+    let b_children = b.children();
+    if a_children.is_empty() && b_children.is_empty() {
+        return a.to_string() == b.to_string();
+    }
+    if !a.is::<ExpressionStatement>() && a.token_literal() != b.token_literal() {
+        return false;
+    }
+    return a_children.len() == b_children.len()
+        && a_children
+            .iter()
+            .zip(b_children.iter())
+            .all(|(x, y)| ast_equal(*x, *y));
+}
+
+fn ungrouped(node: &dyn ProgramNode) -> &dyn ProgramNode {
+    let grouped = node.downcast_ref::<GroupedExpression>();
+    if grouped.is_some() {
+        return ungrouped(grouped.unwrap().expression.as_ref());
+    }
+    return node;
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+// Folds a node into the names it binds and the identifiers it reads. Let
+// names and parameters are bindings rather than reads.
+fn bound_and_used_names(
+    node: &dyn ProgramNode,
+    children: Vec<(Vec<String>, Vec<String>)>,
+) -> (Vec<String>, Vec<String>) {
+    let identifier = node.downcast_ref::<IdentifierExpression>();
+    if identifier.is_some() {
+        return (vec![], vec![identifier.unwrap().value.clone()]);
+    }
+    let rest = node.downcast_ref::<RestParameterExpression>();
+    if rest.is_some() {
+        return (vec![rest.unwrap().value.clone()], vec![]);
+    }
+
+    // Positions of the children that name what is being bound
+    let binding_children = if node.is::<LetStatement>() || node.is::<LetInExpression>() {
+        0..1
+    } else if node.is::<TryExpression>() {
+        1..2
+    } else if node.is::<FunctionLiteralExpression>() {
+        0..children.len() - 1
+    } else {
+        0..0
+    };
+
+    let mut bound: Vec<String> = vec![];
+    let mut used: Vec<String> = vec![];
+    for (idx, child) in children.into_iter().enumerate() {
+        bound.extend(child.0);
+        if binding_children.contains(&idx) {
+            bound.extend(child.1);
+        } else {
+            used.extend(child.1);
+        }
+    }
+    return (bound, used);
+}
+
+// A return leaves the block it is in, so nothing after it in that block runs.
+fn unreachable_after_return<'a>(
+    statements: impl Iterator<Item = &'a dyn ProgramNode>,
+) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut returned = false;
+    for statement in statements {
+        if returned {
+            warnings.push(format!(
+                "unreachable statement after return: {}",
+                statement.to_string()
+            ));
+        }
+        if statement.token_literal().unwrap() == "return" {
+            returned = true;
+        }
+    }
+    return warnings;
+}
+
+// How many if expressions deep the most nested branch in the program sits.
+pub fn max_if_depth(statements: &[Box<dyn ProgramNode>]) -> usize {
+    let depth = |node: &dyn ProgramNode, children: Vec<usize>| {
+        let deepest = children.into_iter().max().unwrap_or(0);
+        if node.is::<IfExpression>() {
+            return deepest + 1;
+        }
+        return deepest;
+    };
+    return statements
+        .iter()
+        .map(|statement| fold(statement.as_ref(), &depth))
+        .max()
+        .unwrap_or(0);
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use crate::types::Integer;
+    use crate::ast::Parser;
+    use crate::lexer::Lexer;
+    use crate::types::{Array, Integer};
 
     struct Test {
         value: i64,
@@ -103,8 +500,11 @@ mod tests {
             return Some(Box::new(Integer { value: self.value }));
         }
 
-        fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-            return Some(vec![("Test".to_string(), Box::new(Integer { value: 5 }))]);
+        fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+            return Ok(Some(vec![(
+                "Test".to_string(),
+                Box::new(Integer { value: 5 }),
+            )]));
         }
 
         fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -125,4 +525,399 @@ mod tests {
             .list_keys()
             .contains(&&"Test".to_string()));
     }
+
+    #[test]
+    fn test_profile() {
+        let input =
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10)";
+        let lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        program.profiling = true;
+        assert_eq!(program.eval().unwrap().inspect(), "55");
+
+        let profile = program.profile();
+        // fib is called 177 times for fib(10), every call but the first from
+        // inside the function body
+        assert_eq!(profile["CallExpression"], 177);
+        assert_eq!(profile["IfExpression"], 177);
+        assert!(profile["InfixExpression"] > profile["CallExpression"]);
+        assert!(profile["IdentifierExpression"] > 0);
+        assert!(!profile.contains_key("WhileExpression"));
+
+        program.reset_profile();
+        assert!(program.profile().is_empty());
+
+        // Nothing is counted unless profiling is on
+        let lexer = Lexer::new("1 + 2".to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        program.eval();
+        assert!(program.profile().is_empty());
+    }
+
+    #[test]
+    fn test_clone_fresh() {
+        let lexer = Lexer::new("let x = 5; let y = x * 2; y".to_string());
+        let mut parser = Parser::new(lexer);
+        let program = Program::new(parser.parse());
+
+        let mut first = program.clone_fresh();
+        let mut second = program.clone_fresh();
+        assert_eq!(first.eval().unwrap().inspect(), "10");
+        assert!(first.environment.has_key("y"));
+        assert!(!second.environment.has_key("x"));
+        assert!(!program.environment.has_key("x"));
+
+        assert_eq!(second.statements.len(), 3);
+        assert_eq!(second.eval().unwrap().inspect(), "10");
+        assert!(first.eval().is_none());
+        assert_eq!(first.clone_fresh().eval().unwrap().inspect(), "10");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let lexer = Lexer::new(
+            "let f = fn(n) { if (n < 1) { 0 } else { f(n - 1) + f(n - 1) } }; f(40)".to_string(),
+        );
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        program.timeout = Some(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let result = program.eval();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(is_error_option(&result));
+        assert_eq!(result.unwrap().inspect(), "evaluation timed out");
+
+        let lexer = Lexer::new("let x = 5; x * 2".to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        program.timeout = Some(Duration::from_secs(5));
+        assert_eq!(program.eval().unwrap().inspect(), "10");
+    }
+
+    #[test]
+    fn test_arithmetic_mode() {
+        let test_inputs = vec![
+            (
+                ArithmeticMode::Checked,
+                "9223372036854775807 + 1",
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                "9223372036854775807 + 1",
+                "-9223372036854775808",
+            ),
+            (
+                ArithmeticMode::Checked,
+                "let min = -9223372036854775807 - 1; -min",
+                "integer overflow: --9223372036854775808",
+            ),
+            (
+                ArithmeticMode::Checked,
+                "-(-9223372036854775808)",
+                "integer overflow: --9223372036854775808",
+            ),
+            (
+                ArithmeticMode::Checked,
+                "let min = -9223372036854775808; [min, min - 1]",
+                "integer overflow: -9223372036854775808 - 1",
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                "-(-9223372036854775808)",
+                "-9223372036854775808",
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                "let min = -9223372036854775807 - 1; min / -1",
+                "-9223372036854775808",
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                "let f = fn(x) { x * 2 }; f(9223372036854775807)",
+                "-2",
+            ),
+            (ArithmeticMode::Wrapping, "1 / 0", "division by zero"),
+            (ArithmeticMode::Wrapping, "3 ** 64", "8733086111712066817"),
+            (ArithmeticMode::Checked, "2 * 3 - 1", "5"),
+        ];
+
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.1.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            program.arithmetic_mode = test_input.0;
+            assert_eq!(program.eval().unwrap().inspect(), test_input.2);
+        }
+    }
+
+    #[test]
+    fn test_fold() {
+        let test_inputs = vec![
+            ("1 + 2", 0, 0),
+            ("if (true) { 1 }", 1, 0),
+            (
+                "if (true) { if (false) { 1 } else { if (true) { 2 } } }; if (true) { 3 }",
+                3,
+                0,
+            ),
+            (
+                "let f = fn(x) { if (x) { fn() { 1 } } }; f(if (true) { 1 })",
+                1,
+                2,
+            ),
+            (
+                "[fn() { 1 }, {\"a\": fn(y) { if (y) { if (y) { 1 } } }}]",
+                2,
+                2,
+            ),
+        ];
+
+        let count_functions = |node: &dyn ProgramNode, children: Vec<usize>| {
+            let own = node.is::<FunctionLiteralExpression>() as usize;
+            return own + children.into_iter().sum::<usize>();
+        };
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let statements = parser.parse();
+            assert_eq!(max_if_depth(&statements), test_input.1);
+
+            let functions: usize = statements
+                .iter()
+                .map(|statement| fold(statement.as_ref(), &count_functions))
+                .sum();
+            assert_eq!(functions, test_input.2);
+        }
+    }
+
+    #[test]
+    fn test_check_unreachable() {
+        let test_inputs = vec![
+            ("return 1; 2", vec!["unreachable statement after return: 2"]),
+            (
+                "let x = 1; return x; let y = 2; y",
+                vec![
+                    "unreachable statement after return: let y = 2",
+                    "unreachable statement after return: y",
+                ],
+            ),
+            (
+                "let f = fn() { return 1; 2 }; f()",
+                vec!["unreachable statement after return: 2"],
+            ),
+            ("if (true) { return 1 } else { return 2 }; 3", vec![]),
+            (
+                "if (true) { if (false) { return 1; 5 } }",
+                vec!["unreachable statement after return: 5"],
+            ),
+            ("let f = fn() { 1; return 2 }; f()", vec![]),
+        ];
+
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let program = Program::new(parser.parse());
+            let warnings: Vec<String> = program
+                .check()
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect();
+            assert_eq!(warnings, test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_undefined_is_null() {
+        let test_inputs = vec![
+            (false, "missing", "unknown identifier: missing"),
+            (true, "missing", "null"),
+            (true, "let x = missing; is_null(x)", "true"),
+            (true, "let f = fn() { missing }; f()", "null"),
+            (true, "len(\"ab\")", "2"),
+            (true, "missing + 1", "type mismatch: NULL + INTEGER"),
+        ];
+
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.1.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            program.undefined_is_null = test_input.0;
+            assert_eq!(program.eval().unwrap().inspect(), test_input.2);
+        }
+    }
+
+    #[test]
+    fn test_ast_equal() {
+        let parse = |src: &str| Parser::new(Lexer::new(src.to_string())).parse();
+        let test_inputs = vec![
+            ("let x=1+2*3;", "let   x = 1 + (2 * 3)\n;", true),
+            ("fn(a,b){a+b}(1,2)", "fn(a, b) {\n  a + b\n}(1, 2)", true),
+            (
+                "if (x) {1} else {2}",
+                "if (x) {\n  1\n} else {\n  2\n}",
+                true,
+            ),
+            ("(x) + ((1))", "x + 1", true),
+            ("1 + 2 * 3", "(1 + 2) * 3", false),
+            ("1 + 2", "1 - 2", false),
+            ("let x = 1", "const x = 1", false),
+            ("\"1\"", "1", false),
+            ("let x = 1", "let y = 1", false),
+            ("[1, 2]", "[1, 2, 3]", false),
+        ];
+
+        for test_input in test_inputs {
+            let a = parse(test_input.0);
+            let b = parse(test_input.1);
+            assert_eq!(a.len(), b.len());
+            assert_eq!(
+                ast_equal(a[0].as_ref(), b[0].as_ref()),
+                test_input.2,
+                "{}",
+                test_input.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let lexer = Lexer::new("1 + 2".to_string());
+        let mut parser = Parser::new(lexer);
+        let program = Program::new(parser.parse());
+        let infix = concat!(
+            "{\"kind\":\"InfixExpression\",\"operator\":\"+\",",
+            "\"left\":{\"kind\":\"IntegerLiteralExpression\",\"value\":1},",
+            "\"right\":{\"kind\":\"IntegerLiteralExpression\",\"value\":2}}"
+        );
+        assert_eq!(
+            program.to_json(),
+            format!(
+                "[{{\"kind\":\"ExpressionStatement\",\"expression\":{}}}]",
+                infix
+            )
+        );
+
+        let lexer = Lexer::new("let s = \"say \\\"hi\\\"\\n\"; !s".to_string());
+        let mut parser = Parser::new(lexer);
+        let program = Program::new(parser.parse());
+        assert_eq!(
+            program.statements[0].to_json(),
+            concat!(
+                "{\"kind\":\"LetStatement\",\"token\":\"let\",\"children\":[",
+                "{\"kind\":\"IdentifierExpression\",\"value\":\"s\"},",
+                "{\"kind\":\"StringLiteralExpression\",\"value\":\"say \\\"hi\\\"\\n\"}]}"
+            )
+        );
+        assert_eq!(
+            program.statements[1].to_json(),
+            concat!(
+                "{\"kind\":\"ExpressionStatement\",\"expression\":",
+                "{\"kind\":\"PrefixExpression\",\"operator\":\"!\",",
+                "\"right\":{\"kind\":\"IdentifierExpression\",\"value\":\"s\"}}}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_max_collection_size() {
+        let test_inputs = vec![
+            ("[1, 2, 3]", "[1, 2, 3]"),
+            ("[1, 2, 3, 4]", "collection size limit exceeded: 4 > 3"),
+            (
+                "{1: 1, 2: 2, 3: 3, 4: 4}",
+                "collection size limit exceeded: 4 > 3",
+            ),
+            ("[1, 2] + [3, 4]", "collection size limit exceeded: 4 > 3"),
+            // Sizes are checked before anything is built
+            ("[1, 2, 3, foo]", "collection size limit exceeded: 4 > 3"),
+            ("push([1, 2], 3)", "[1, 2, 3]"),
+            (
+                "push([1, 2, 3], 4)",
+                "collection size limit exceeded: 4 > 3",
+            ),
+            ("let f = fn(xs) { push(xs, 0) }; f(f(f([])))", "[0, 0, 0]"),
+            (
+                "let f = fn(xs) { push(xs, 0) }; f(f(f(f([]))))",
+                "collection size limit exceeded: 4 > 3",
+            ),
+        ];
+
+        for test_input in test_inputs {
+            let lexer = Lexer::new(test_input.0.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            program.max_collection_size = 3;
+            assert_eq!(program.eval().unwrap().inspect(), test_input.1);
+        }
+
+        let lexer = Lexer::new("[1, 2, 3, 4]".to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        assert_eq!(program.eval().unwrap().inspect(), "[1, 2, 3, 4]");
+    }
+
+    #[test]
+    fn test_eval_collect() {
+        let inspect_all = |src: &str| {
+            let lexer = Lexer::new(src.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            return program
+                .eval_collect()
+                .iter()
+                .map(|x| x.as_ref().map(|x| x.inspect()))
+                .collect::<Vec<Option<String>>>();
+        };
+
+        let some = |x: &str| Some(x.to_string());
+        assert_eq!(
+            inspect_all("1; 2; 3"),
+            vec![some("1"), some("2"), some("3")]
+        );
+        assert_eq!(
+            inspect_all("let x = 2; x * 2; x"),
+            vec![None, some("4"), some("2")]
+        );
+        assert_eq!(
+            inspect_all("1; foo; 3"),
+            vec![some("1"), some("unknown identifier: foo")]
+        );
+        assert_eq!(
+            inspect_all("const x = 1; let x = 2; 3"),
+            vec![None, some("cannot reassign constant: x")]
+        );
+        assert_eq!(inspect_all(""), Vec::<Option<String>>::new());
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        let run = |seed: u64| {
+            let source =
+                "let roll = fn() { random(6) }; [random(1000), roll(), roll(), random(1000)]";
+            let lexer = Lexer::new(source.to_string());
+            let mut parser = Parser::new(lexer);
+            let mut program = Program::new(parser.parse());
+            program.set_seed(seed);
+            return program.eval().unwrap().inspect();
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+
+        let lexer =
+            Lexer::new("[random(3), random(3), random(3), random(3), random(3)]".to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = Program::new(parser.parse());
+        program.set_seed(1);
+        let result = program.eval().unwrap();
+        let rolls = result.downcast_ref::<Array>().unwrap();
+        for roll in &rolls.elements {
+            let value = roll.downcast_ref::<Integer>().unwrap().value;
+            assert!((0..3).contains(&value));
+        }
+    }
 }
@@ -1,59 +1,748 @@
 use crate::ast::Parser;
+use crate::builtins::{list_builtins, read_input, set_output};
+use crate::diagnostics::syntax_error;
+use crate::environment::Environment;
 use crate::lexer::Lexer;
 use crate::program::{Program, ProgramNode};
-use crate::statements::is_error;
-use std::io::{stdin, stdout, Write};
+use crate::types::{is_error_option, Object};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{stderr, stdout, Write};
+use std::rc::Rc;
+
+const HELP: &str = "\
+Variables    let x = 5;   const y = 1;   let x = 1 in x + 1   (local to the expression)
+Functions    let add = fn(a, b) { a + b };   fn(first, ...rest) { rest }
+Calls        add(1, 2)   5 |> double   (pipe the value into a call)
+Control      if (x > 1) { a } else { b }   if x > 1 then a else b   while (c) { ... }   do { ... } while (c)
+             return a, b   (returns [a, b])
+             match (x) { 1 => a, 2..5 => b, _ => c }   (ranges include both ends)
+             try { ... } catch (e) { e }   (e is what was thrown, or the error message)   throw \"message\"
+Values       1  true  \"text\"  r\"raw\"  `hi ${name}`  [1, 2]  {\"key\": 1}   `if` (keyword as a name)
+Modules      import \"lib.blang\"   (brings in its top-level lets)
+Indexing     xs[0]   hash[\"key\"]
+Operators    + - * / **   == != < > <= >=   && ||   ?? (fallback for null or errors)   ! -
+Builtins     :builtins lists them
+Commands     :help  :exit  :builtins  :save <file>  :undo  :verbose on|off  :profile on|off  :seed <n>  :maxlen <n>";
+
+// Counts the brackets, braces and parentheses left open in `input`, ignoring
+// any inside string literals.
+pub fn open_delimiters(input: &str) -> usize {
+    let mut open: usize = 0;
+    let mut in_string = false;
+    let mut chars = input.chars();
+    while let Some(ch) = chars.next() {
+        if in_string {
+            if ch == '\\' {
+                chars.next();
+            } else if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            '(' | '{' | '[' => open += 1,
+            ')' | '}' | ']' => open = open.saturating_sub(1),
+            _ => {}
+        }
+    }
+    return open;
+}
+
+// How many characters of a result are echoed unless set with :maxlen.
+pub const DEFAULT_MAX_DISPLAY_LEN: usize = 10_000;
+
+// Cuts `text` down to `max_len` characters, saying how many were left off.
+pub fn truncate(text: &str, max_len: usize) -> String {
+    let total = text.chars().count();
+    if total <= max_len {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_len).collect();
+    return format!("{}… ({} more)", kept, total - max_len);
+}
+
+// The profile of the last evaluation, busiest node types first.
+fn profile_lines(program: &Program) -> Vec<String> {
+    let mut counts: Vec<(String, usize)> = program.profile().into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    return counts
+        .iter()
+        .map(|(node_type, count)| format!("{}: {}", node_type, count))
+        .collect();
+}
+
+// A line the REPL reports back, errors kept apart so they can go to stderr.
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    Value(String),
+    Error(String),
+}
+
+// Lets puts write to the same stream the REPL echoes results to.
+struct SharedWriter(Rc<RefCell<Box<dyn Write>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        return self.0.borrow_mut().write(buf);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.0.borrow_mut().flush();
+    }
+}
 
 pub struct REPL {
     prompt: String,
+    // Source of every statement that evaluated without an error
+    accepted: Vec<String>,
+    // The environment as it was before each accepted statement, for :undo
+    snapshots: Vec<Environment>,
+    // Echo results along with their type
+    verbose: bool,
+    // Longest result echoed in full, 0 for no limit
+    max_display_len: usize,
+    // Cleared by :exit to end the run loop
+    running: bool,
+    // Results and puts output go to stdout, errors to stderr
+    stdout: Rc<RefCell<Box<dyn Write>>>,
+    stderr: Box<dyn Write>,
 }
 
 impl REPL {
     pub fn new(prompt: String) -> REPL {
-        return REPL { prompt };
+        let stdout: Rc<RefCell<Box<dyn Write>>> = Rc::new(RefCell::new(Box::new(stdout())));
+        return REPL {
+            prompt,
+            accepted: vec![],
+            snapshots: vec![],
+            verbose: false,
+            max_display_len: DEFAULT_MAX_DISPLAY_LEN,
+            running: true,
+            stdout,
+            stderr: Box::new(stderr()),
+        };
     }
 
-    fn read(&self) -> Vec<Box<dyn ProgramNode>> {
-        print!("{}", self.prompt);
+    // Replaces the stream results are echoed to, which puts also writes to.
+    pub fn set_stdout(&mut self, writer: Box<dyn Write>) {
+        *self.stdout.borrow_mut() = writer;
+    }
+
+    // Replaces the stream errors are reported on.
+    pub fn set_stderr(&mut self, writer: Box<dyn Write>) {
+        self.stderr = writer;
+    }
+
+    // None once stdin is closed, e.g. by Ctrl-D.
+    fn read(&self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
         let mut s = String::new();
         let _ = stdout().flush();
-        stdin().read_line(&mut s).expect("Did not enter a string");
+        let read = read_input(&mut s).expect("Did not enter a string");
+        if read == 0 {
+            return None;
+        }
+
+        return Some(s);
+    }
+
+    // Lines starting with ':' are commands for the REPL itself, everything
+    // else is evaluated as code.
+    fn handle(&mut self, program: &mut Program, input: String) -> Vec<Output> {
+        let command = input.trim();
+        if command.starts_with(':') {
+            return self.run_command(program, command);
+        }
+
+        let error = syntax_error(&input);
+        if error.is_some() {
+            return vec![Output::Error(error.unwrap().inspect())];
+        }
+
+        let statements = self.parse(input);
+        let mut outputs = self.eval(program, statements);
+        if program.profiling {
+            outputs.extend(profile_lines(program).into_iter().map(Output::Value));
+            program.reset_profile();
+        }
+        return outputs;
+    }
+
+    fn run_command(&mut self, program: &mut Program, command: &str) -> Vec<Output> {
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            ":help" => {
+                return HELP
+                    .lines()
+                    .map(|line| Output::Value(line.to_string()))
+                    .collect();
+            }
+            ":exit" | ":quit" => {
+                self.running = false;
+                return vec![];
+            }
+            ":builtins" => {
+                return list_builtins()
+                    .iter()
+                    .map(|def| Output::Value(format!("{:<10} {}", def.name, def.description)))
+                    .collect();
+            }
+            ":save" => return vec![Output::Value(self.save(argument.trim()))],
+            ":undo" => return vec![Output::Value(self.undo(program))],
+            ":verbose" => return vec![Output::Value(self.set_verbose(argument.trim()))],
+            ":profile" => match argument.trim() {
+                "on" => program.profiling = true,
+                "off" => program.profiling = false,
+                _ => return vec![Output::Value("usage: :profile on|off".to_string())],
+            },
+            ":maxlen" => match argument.trim().parse::<usize>() {
+                Ok(max_len) => {
+                    self.max_display_len = max_len;
+                    return vec![Output::Value(format!("maxlen {}", max_len))];
+                }
+                Err(_) => return vec![Output::Value("usage: :maxlen <n>".to_string())],
+            },
+            ":seed" => match argument.trim().parse::<u64>() {
+                Ok(seed) => {
+                    program.set_seed(seed);
+                    return vec![Output::Value(format!("seed {}", seed))];
+                }
+                Err(_) => return vec![Output::Value("usage: :seed <n>".to_string())],
+            },
+            _ => return vec![Output::Error(format!("unknown command: {}", command))],
+        }
+        return vec![Output::Value(format!("profile {}", argument.trim()))];
+    }
 
-        let lexer = Lexer::new(s);
+    // Writes the accepted statements out as a script that can be run again.
+    fn save(&self, path: &str) -> String {
+        if path.len() == 0 {
+            return "usage: :save <file>".to_string();
+        }
+
+        let mut script = String::new();
+        for source in &self.accepted {
+            script.push_str(source);
+            script.push_str(";\n");
+        }
+
+        match fs::write(path, script) {
+            Ok(_) => return format!("saved {} statements to {}", self.accepted.len(), path),
+            Err(error) => return format!("could not save to {}: {}", path, error),
+        }
+    }
+
+    // Removes the last accepted statement and restores the environment from
+    // before it ran.
+    fn undo(&mut self, program: &mut Program) -> String {
+        if self.accepted.len() == 0 {
+            return "nothing to undo".to_string();
+        }
+
+        program.environment = self.snapshots.pop().unwrap();
+        return format!("undid: {}", self.accepted.pop().unwrap());
+    }
+
+    fn set_verbose(&mut self, argument: &str) -> String {
+        match argument {
+            "on" => self.verbose = true,
+            "off" => self.verbose = false,
+            _ => return "usage: :verbose on|off".to_string(),
+        }
+        return format!("verbose {}", argument);
+    }
+
+    // How a result is echoed, e.g. `10` or `10 : INTEGER` in verbose mode.
+    fn format_result(&self, result: &dyn Object) -> String {
+        let mut text = result.inspect();
+        if self.max_display_len > 0 {
+            text = truncate(&text, self.max_display_len);
+        }
+        if self.verbose {
+            return format!("{} : {:?}", text, result.type_());
+        }
+        return text;
+    }
+
+    fn parse(&self, input: String) -> Vec<Box<dyn ProgramNode>> {
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         return parser.parse();
     }
-    // fn eval(&self, program: &Program) {}
-    // fn print(&self, program: &Program) {
-    //     for statement in &program.statements {
-    //         println!("{}", statement.to_string());
-    //     }
-    // }
 
-    pub fn run(&self) {
+    // Evaluates each new statement on its own, so every statement entered on
+    // a line reports its own result.
+    fn eval(
+        &mut self,
+        program: &mut Program,
+        statements: Vec<Box<dyn ProgramNode>>,
+    ) -> Vec<Output> {
+        let mut outputs: Vec<Output> = vec![];
+        for statement in statements {
+            let source = statement.to_string();
+            let snapshot = program.environment.get_copy();
+            program.extend(vec![statement]);
+            let result = program.eval();
+
+            if is_error_option(&result) {
+                outputs.push(Output::Error(result.as_ref().unwrap().inspect()));
+                program.walk_back_error();
+                program.discard_evaluated();
+                continue;
+            }
+            program.discard_evaluated();
+
+            self.accepted.push(source.trim_end_matches(';').to_string());
+            self.snapshots.push(snapshot);
+            if result.is_some() {
+                outputs.push(Output::Value(self.format_result(result.unwrap().as_ref())));
+            }
+        }
+        return outputs;
+    }
+
+    // Handles `input` and writes what it reports to stdout or stderr. puts
+    // writes to the REPL's stdout only while the input is being handled.
+    fn respond(&mut self, program: &mut Program, input: String) {
+        let previous = set_output(Box::new(SharedWriter(self.stdout.clone())));
+        let outputs = self.handle(program, input);
+        set_output(previous);
+        for output in outputs {
+            let _ = match output {
+                Output::Value(text) => writeln!(self.stdout.borrow_mut(), "{}", text),
+                Output::Error(text) => writeln!(self.stderr, "{}", text),
+            };
+        }
+        let _ = self.stdout.borrow_mut().flush();
+        let _ = self.stderr.flush();
+    }
+
+    pub fn run(&mut self) {
         let text_logo = r#"___.   .__                         
 \_ |__ |  | _____    ____    ____  
  | __ \|  | \__  \  /    \  / ___\ 
  | \_\ \  |__/ __ \|   |  \/ /_/  >
  |___  /____(____  /___|  /\___  / 
      \/          \/     \//_____/  "#;
-        println!("");
+        println!();
         println!("{}", text_logo);
 
         println!("\nWelcome to BLANG, An Interpreter for the Monkey Language written in Rust!\n");
         let mut program = Program::new(vec![]);
-        loop {
-            let statements = self.read();
-            program.extend(statements);
-            let result = program.eval();
-
-            if result.as_ref().is_some() {
-                println!("{}", result.as_ref().unwrap().inspect());
+        let mut input = String::new();
+        while self.running {
+            // Keep reading lines while delimiters are open, showing how many
+            let open = open_delimiters(&input);
+            let line = if open > 0 {
+                self.read(&format!("...{}> ", open))
+            } else {
+                self.read(&self.prompt)
+            };
+            if line.is_none() {
+                println!();
+                break;
+            }
+            input.push_str(&line.unwrap());
 
-                if is_error(result.as_ref()) {
-                    program.walk_back_error();
-                }
+            if !input.trim().starts_with(':') && open_delimiters(&input) > 0 {
+                continue;
             }
+            self.respond(&mut program, input);
+            input = String::new();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::puts_lines;
+    use crate::runner::eval_string;
+    use crate::types::StringLiteral;
+
+    fn value(text: &str) -> Output {
+        return Output::Value(text.to_string());
+    }
+
+    fn error(text: &str) -> Output {
+        return Output::Error(text.to_string());
+    }
+
+    #[test]
+    fn test_multiple_results() {
+        let test_inputs = vec![
+            ("1 + 1; 2 + 2", vec![value("2"), value("4")]),
+            ("1+1; 2+2; 3+3", vec![value("2"), value("4"), value("6")]),
+            ("let x = 5; x * 2; x", vec![value("10"), value("5")]),
+            (
+                "1; foo; 3",
+                vec![value("1"), error("unknown identifier: foo"), value("3")],
+            ),
+            ("let y = 1;", vec![]),
+            (
+                "let z = foo; 1",
+                vec![error("unknown identifier: foo"), value("1")],
+            ),
+        ];
+
+        let mut repl = REPL::new(">> ".to_string());
+        for test_input in test_inputs {
+            let mut program = Program::new(vec![]);
+            let statements = repl.parse(test_input.0.to_string());
+            assert_eq!(repl.eval(&mut program, statements), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_echo_inspects() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, "\"hi\"".to_string());
+        assert_eq!(outputs, vec![value("\"hi\"")]);
+        let outputs = repl.handle(&mut program, "let s = \"a\\nb\"; [s, 1]; s".to_string());
+        assert_eq!(outputs, vec![value("[\"a\\nb\", 1]"), value("\"a\\nb\"")]);
+
+        // puts prints the raw text and evaluates to null
+        let outputs = repl.handle(&mut program, "puts(\"hi\")".to_string());
+        assert_eq!(outputs, vec![value("null")]);
+        let args: Vec<Box<dyn Object>> = vec![Box::new(StringLiteral {
+            value: "hi".to_string(),
+        })];
+        assert_eq!(puts_lines(&args), vec!["hi"]);
+    }
+
+    #[test]
+    fn test_verbose_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, ":verbose on".to_string());
+        assert_eq!(outputs, vec![value("verbose on")]);
+        let test_inputs = vec![
+            ("10", "10 : INTEGER"),
+            ("\"hi\"", "\"hi\" : STRING"),
+            ("[1, true]", "[1, true] : ARRAY"),
+            ("1 > 2", "false : BOOLEAN"),
+            ("if (false) { 1 }", "null : NULL"),
+        ];
+        for test_input in test_inputs {
+            let outputs = repl.handle(&mut program, test_input.0.to_string());
+            assert_eq!(outputs, vec![value(test_input.1)]);
+        }
+
+        // Errors are reported as they are
+        let outputs = repl.handle(&mut program, "foo".to_string());
+        assert_eq!(outputs, vec![error("unknown identifier: foo")]);
+
+        repl.handle(&mut program, ":verbose off".to_string());
+        assert_eq!(
+            repl.handle(&mut program, "10".to_string()),
+            vec![value("10")]
+        );
+        let outputs = repl.handle(&mut program, ":verbose".to_string());
+        assert_eq!(outputs, vec![value("usage: :verbose on|off")]);
+    }
+
+    #[test]
+    fn test_statements_are_discarded() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        for i in 0..100 {
+            repl.handle(&mut program, format!("let x{} = {}; x{} + 1; foo", i, i, i));
+            assert_eq!(program.statements.len(), 0);
+        }
+        let outputs = repl.handle(&mut program, "x0 + x99".to_string());
+        assert_eq!(outputs, vec![value("99")]);
+        assert_eq!(program.statements.len(), 0);
+    }
+
+    #[test]
+    fn test_syntax_error_context() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, "let x = 1 $ 2;\n".to_string());
+        assert_eq!(
+            outputs,
+            vec![error("error: unexpected character `$`\n --> 1:11\n  |\n1 | let x = 1 $ 2;\n  |           ^")]
+        );
+        assert_eq!(program.statements.len(), 0);
+
+        let outputs = repl.handle(&mut program, "let = 5\n".to_string());
+        assert_eq!(
+            outputs,
+            vec![error("error: Expected next token to be IDENT, got ASSIGN instead\n --> 1:5\n  |\n1 | let = 5\n  |     ^")]
+        );
+        assert_eq!(program.statements.len(), 0);
+    }
+
+    #[test]
+    fn test_profile_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, ":profile on".to_string());
+        assert_eq!(outputs, vec![value("profile on")]);
+        let outputs = repl.handle(&mut program, "1 + 2 * 3".to_string());
+        assert_eq!(
+            outputs,
+            vec![
+                value("7"),
+                value("IntegerLiteralExpression: 3"),
+                value("InfixExpression: 2"),
+                value("ExpressionStatement: 1")
+            ]
+        );
+
+        // Counts start again for every input
+        let outputs = repl.handle(&mut program, "4".to_string());
+        assert_eq!(
+            outputs,
+            vec![
+                value("4"),
+                value("ExpressionStatement: 1"),
+                value("IntegerLiteralExpression: 1")
+            ]
+        );
+
+        repl.handle(&mut program, ":profile off".to_string());
+        assert_eq!(repl.handle(&mut program, "4".to_string()), vec![value("4")]);
+        let outputs = repl.handle(&mut program, ":profile".to_string());
+        assert_eq!(outputs, vec![value("usage: :profile on|off")]);
+    }
+
+    #[test]
+    fn test_seed_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, ":seed 42".to_string());
+        assert_eq!(outputs, vec![value("seed 42")]);
+        let first = repl.handle(&mut program, "[random(100), random(100)]".to_string());
+        repl.handle(&mut program, ":seed 42".to_string());
+        let second = repl.handle(&mut program, "[random(100), random(100)]".to_string());
+        assert_eq!(first, second);
+
+        let outputs = repl.handle(&mut program, ":seed -1".to_string());
+        assert_eq!(outputs, vec![value("usage: :seed <n>")]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let long = "x".repeat(25);
+        assert_eq!(truncate(&long, 10), "xxxxxxxxxx… (15 more)");
+        assert_eq!(truncate(&long, 25), long);
+        assert_eq!(truncate("héllo wörld", 4), "héll… (7 more)");
+        assert_eq!(truncate("", 0), "");
+    }
+
+    #[test]
+    fn test_maxlen_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, ":maxlen 8".to_string());
+        assert_eq!(outputs, vec![value("maxlen 8")]);
+        let outputs = repl.handle(&mut program, "[1, 2, 3, 4, 5]; 7".to_string());
+        assert_eq!(outputs, vec![value("[1, 2, 3… (7 more)"), value("7")]);
+        repl.handle(&mut program, ":verbose on".to_string());
+        let outputs = repl.handle(&mut program, "\"abcdefghij\"".to_string());
+        assert_eq!(outputs, vec![value("\"abcdefg… (4 more) : STRING")]);
+
+        repl.handle(&mut program, ":maxlen 0".to_string());
+        let outputs = repl.handle(&mut program, "\"abcdefghij\"".to_string());
+        assert_eq!(outputs, vec![value("\"abcdefghij\" : STRING")]);
+        let outputs = repl.handle(&mut program, ":maxlen".to_string());
+        assert_eq!(outputs, vec![value("usage: :maxlen <n>")]);
+    }
+
+    #[test]
+    fn test_exit_command() {
+        for command in [":exit", ":quit\n"] {
+            let mut repl = REPL::new(">> ".to_string());
+            let mut program = Program::new(vec![]);
+            assert!(repl.running);
+
+            let outputs = repl.handle(&mut program, command.to_string());
+            assert!(outputs.is_empty());
+            assert!(!repl.running);
+        }
+    }
+
+    // A buffer the test can still read after handing the REPL a writer.
+    #[derive(Clone)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            return self.0.borrow_mut().write(buf);
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            return String::from_utf8(self.0.borrow().clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_output_streams() {
+        let outside = Captured(Rc::new(RefCell::new(vec![])));
+        set_output(Box::new(outside.clone()));
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+        let out = Captured(Rc::new(RefCell::new(vec![])));
+        let err = Captured(Rc::new(RefCell::new(vec![])));
+        repl.set_stdout(Box::new(out.clone()));
+        repl.set_stderr(Box::new(err.clone()));
+
+        repl.respond(&mut program, "puts(\"hi\"); foo; 1 + 1".to_string());
+        repl.respond(&mut program, "let x = 1 $ 2;".to_string());
+        repl.respond(&mut program, ":nope".to_string());
+        assert_eq!(out.text(), "hi\nnull\n2\n");
+        assert!(err
+            .text()
+            .starts_with("unknown identifier: foo\nerror: unexpected character `$`\n"));
+        assert!(err.text().ends_with("unknown command: :nope\n"));
+
+        // Each REPL gets puts only while it handles its own input
+        let mut other = REPL::new(">> ".to_string());
+        let other_out = Captured(Rc::new(RefCell::new(vec![])));
+        other.set_stdout(Box::new(other_out.clone()));
+        other.respond(&mut Program::new(vec![]), "puts(\"other\");".to_string());
+        eval_string("puts(\"outside\")");
+        drop(other);
+        repl.respond(&mut program, "puts(\"again\");".to_string());
+        assert_eq!(out.text(), "hi\nnull\n2\nagain\nnull\n");
+        assert_eq!(other_out.text(), "other\nnull\n");
+        assert_eq!(outside.text(), "outside\n");
+        set_output(Box::new(stdout()));
+    }
+
+    #[test]
+    fn test_open_delimiters() {
+        let test_inputs = vec![
+            ("let x = 5;", 0),
+            ("let f = fn(x) {", 1),
+            ("let f = fn(x) {\n  if (x > 1) {\n", 2),
+            ("let a = [1, {\"k\": (2", 3),
+            ("let f = fn(x) {\n  x\n}", 0),
+            ("let s = \"{ ( [\"; {", 1),
+            ("let s = \"quote \\\" {\"; fn() {", 1),
+            ("}", 0),
+        ];
+        for test_input in test_inputs {
+            assert_eq!(open_delimiters(test_input.0), test_input.1);
+        }
+    }
+
+    #[test]
+    fn test_help_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, ":help\n".to_string());
+        assert!(outputs.len() > 0);
+        assert!(outputs.iter().all(|line| matches!(line, Output::Value(_))));
+        assert!(outputs
+            .iter()
+            .any(|line| matches!(line, Output::Value(text) if text.contains(":undo"))));
+        assert_eq!(program.statements.len(), 0);
+    }
+
+    #[test]
+    fn test_builtins_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        let outputs = repl.handle(&mut program, ":builtins\n".to_string());
+        assert!(outputs
+            .iter()
+            .any(|x| matches!(x, Output::Value(text) if text.starts_with("len "))));
+        assert!(outputs
+            .iter()
+            .any(|x| matches!(x, Output::Value(text) if text.starts_with("puts "))));
+        assert_eq!(outputs.len(), list_builtins().len());
+
+        let outputs = repl.handle(&mut program, ":nope".to_string());
+        assert_eq!(outputs, vec![error("unknown command: :nope")]);
+        assert_eq!(program.statements.len(), 0);
+    }
+
+    #[test]
+    fn test_save_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+        let session = vec![
+            "let x = 5;",
+            "let y = foo;",
+            "let double = fn(n) { n * 2 }; double(x)",
+            "let greeting = \"say \\\"hi\\\"\";",
+            "if (x > 1) { double(x) } else { 0 }",
+        ];
+        for line in session {
+            repl.handle(&mut program, line.to_string());
+        }
+
+        let path = std::env::temp_dir().join(format!("blang_save_{}.blang", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let outputs = repl.handle(&mut program, format!(":save {}", path));
+        assert_eq!(
+            outputs,
+            vec![value(&format!("saved 5 statements to {}", path))]
+        );
+
+        let script = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!script.contains("foo"));
+
+        let result = eval_string(&format!("{}greeting; double(x) + 1", script));
+        assert_eq!(result.unwrap().inspect(), "11");
+        let result = eval_string(&format!("{}greeting", script));
+        assert_eq!(result.unwrap().display(), "say \"hi\"");
+
+        let outputs = repl.handle(&mut program, ":save".to_string());
+        assert_eq!(outputs, vec![value("usage: :save <file>")]);
+    }
+
+    #[test]
+    fn test_undo_command() {
+        let mut repl = REPL::new(">> ".to_string());
+        let mut program = Program::new(vec![]);
+
+        repl.handle(&mut program, "let x = 1;".to_string());
+        repl.handle(&mut program, "let y = 2;".to_string());
+        let outputs = repl.handle(&mut program, ":undo".to_string());
+        assert_eq!(outputs, vec![value("undid: let y = 2")]);
+
+        assert!(program.environment.has_key("x"));
+        assert!(!program.environment.has_key("y"));
+        assert_eq!(program.statements.len(), 0);
+
+        let outputs = repl.handle(&mut program, "x + 1; y".to_string());
+        assert_eq!(outputs, vec![value("2"), error("unknown identifier: y")]);
+
+        let outputs = repl.handle(&mut program, "let x = 5; x".to_string());
+        assert_eq!(outputs, vec![value("5")]);
+        repl.handle(&mut program, ":undo".to_string());
+        repl.handle(&mut program, ":undo".to_string());
+        let outputs = repl.handle(&mut program, "x".to_string());
+        assert_eq!(outputs, vec![value("1")]);
+
+        for _ in 0..3 {
+            repl.handle(&mut program, ":undo".to_string());
         }
+        let outputs = repl.handle(&mut program, ":undo".to_string());
+        assert_eq!(outputs, vec![value("nothing to undo")]);
+        assert_eq!(program.statements.len(), 0);
     }
 }
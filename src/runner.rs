@@ -0,0 +1,294 @@
+use crate::ast::Parser;
+use crate::diagnostics::syntax_error;
+use crate::environment::Environment;
+use crate::lexer::Lexer;
+use crate::program::{Diagnostic, Program, Severity};
+use crate::session::Session;
+use crate::types::{is_error_option, Array, Null, Object, StringLiteral};
+use std::fs;
+use std::io::Read;
+
+// Parses and evaluates a whole program, returning the value of its last
+// statement.
+pub fn eval_string(input: &str) -> Option<Box<dyn Object>> {
+    return eval_with_args(input, &[]);
+}
+
+// Same as `eval_string`, with `args` bound to an array of the given strings.
+pub fn eval_with_args(input: &str, args: &[String]) -> Option<Box<dyn Object>> {
+    let error = syntax_error(input);
+    if error.is_some() {
+        return error;
+    }
+    return load(input, args).eval();
+}
+
+// Evaluates `src` directly against `env`, which keeps any bindings it makes.
+// For embedders holding on to an environment of their own. Errors come back
+// as their messages.
+pub fn eval_in(src: &str, env: &mut Environment) -> Result<Box<dyn Object>, Vec<String>> {
+    let result = eval_source(src, env);
+    if is_error_option(&result) {
+        return Err(vec![result.unwrap().inspect()]);
+    }
+    return Ok(result.unwrap_or(Box::new(Null {})));
+}
+
+// Runs each statement of `src` against `env` in turn, stopping at the first
+// error. None when the last statement has no value, like a `let`.
+pub fn eval_source(src: &str, env: &mut Environment) -> Option<Box<dyn Object>> {
+    let error = syntax_error(src);
+    if error.is_some() {
+        return error;
+    }
+
+    let mut parser = Parser::new(Lexer::new(src.to_string()));
+    let mut result: Option<Box<dyn Object>> = None;
+    for statement in parser.parse() {
+        result = statement.eval(env);
+        if is_error_option(&result) {
+            return result;
+        }
+        let updates = match statement.update_env(env) {
+            Ok(updates) => updates,
+            Err(error) => return Some(error),
+        };
+        for (key, value) in updates.unwrap_or_default() {
+            env.update(key, value);
+        }
+    }
+    return result;
+}
+
+// Parses and statically checks a program without running it, returning what
+// the check found.
+pub fn check_string(input: &str) -> Vec<Diagnostic> {
+    let error = syntax_error(input);
+    if error.is_some() {
+        return vec![Diagnostic {
+            severity: Severity::Error,
+            message: error.unwrap().inspect(),
+        }];
+    }
+    return load(input, &[]).check();
+}
+
+fn load(input: &str, args: &[String]) -> Program {
+    let lexer = Lexer::new(input.to_string());
+    let mut parser = Parser::new(lexer);
+    let mut program = Program::new(parser.parse());
+
+    let mut elements: Vec<Box<dyn Object>> = vec![];
+    for arg in args {
+        elements.push(Box::new(StringLiteral { value: arg.clone() }));
+    }
+    program
+        .environment
+        .update("args".to_string(), Box::new(Array { elements }));
+    return program;
+}
+
+// Runs the script at `path`. Everything after the script path on the command
+// line is passed in as `args`.
+pub fn run_file(path: &str, args: &[String]) -> Result<Option<Box<dyn Object>>, std::io::Error> {
+    let input = fs::read_to_string(path)?;
+    return Ok(eval_with_args(&input, args));
+}
+
+// Evaluates `(name, source)` pairs in order against one environment, so later
+// sources can use what earlier ones defined. Stops at the first error, which
+// is prefixed with the name of the source it came from.
+pub fn eval_sources(sources: &[(&str, &str)]) -> Result<Option<Box<dyn Object>>, String> {
+    let mut session = Session::new();
+    let mut result: Option<Box<dyn Object>> = None;
+    for (name, source) in sources {
+        result = session.eval(source);
+        if is_error_option(&result) {
+            return Err(format!("{}: {}", name, result.unwrap().inspect()));
+        }
+    }
+    return Ok(result);
+}
+
+// Runs several scripts as if they were one, see `eval_sources`.
+pub fn run_files(paths: &[String]) -> Result<Option<Box<dyn Object>>, String> {
+    let mut inputs: Vec<String> = vec![];
+    for path in paths {
+        match fs::read_to_string(path) {
+            Ok(input) => inputs.push(input),
+            Err(error) => return Err(format!("{}: failed to read: {}", path, error)),
+        }
+    }
+
+    let sources: Vec<(&str, &str)> = paths
+        .iter()
+        .zip(inputs.iter())
+        .map(|(path, input)| (path.as_str(), input.as_str()))
+        .collect();
+    return eval_sources(&sources);
+}
+
+// Reads all of `reader` before evaluating it, so a program can be piped in,
+// e.g. `cat prog.blang | blang`.
+pub fn run_reader(mut reader: impl Read) -> Result<Option<Box<dyn Object>>, std::io::Error> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    return Ok(eval_string(&input));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Integer;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_eval_in() {
+        let mut env = Environment::new();
+        env.update("base".to_string(), Box::new(Integer { value: 40 }));
+
+        assert_eq!(eval_in("base + 2", &mut env).unwrap().inspect(), "42");
+        assert_eq!(
+            eval_in("let total = base * 2; const name = \"n\";", &mut env)
+                .unwrap()
+                .inspect(),
+            "null"
+        );
+        assert_eq!(env.get("total").inspect(), "80");
+        assert_eq!(
+            eval_in("[total, name]", &mut env).unwrap().inspect(),
+            "[80, \"n\"]"
+        );
+
+        assert_eq!(
+            eval_in("let x = 1; missing", &mut env).err().unwrap(),
+            vec!["unknown identifier: missing"]
+        );
+        assert_eq!(env.get("x").inspect(), "1");
+        assert_eq!(
+            eval_in("let name = 2", &mut env).err().unwrap(),
+            vec!["cannot reassign constant: name"]
+        );
+        assert!(eval_in("1 # 2", &mut env).err().unwrap()[0].starts_with("error: unexpected"));
+    }
+
+    #[test]
+    fn test_check_string() {
+        let diagnostics =
+            check_string("let x = 1; puts(y); let f = fn(a, ...rest) { a + rest[0] + x }; y");
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                severity: Severity::Error,
+                message: "unknown identifier: y".to_string(),
+            }]
+        );
+
+        let diagnostics = check_string("let f = fn() { return 1; puts(\"never\") }; len(args)");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+
+        assert_eq!(check_string("let n = 2 in n * n"), vec![]);
+        assert_eq!(check_string("try { 1 / 0 } catch (e) { e }"), vec![]);
+        assert_eq!(check_string("import \"lib.blang\"; sq(2)"), vec![]);
+        assert_eq!(
+            check_string("if (true) { import \"lib.blang\" }; sq(2)"),
+            vec![]
+        );
+
+        // Nothing is evaluated, so errors that only happen at runtime pass
+        assert_eq!(check_string("let x = 1 / 0; x(2)"), vec![]);
+
+        let diagnostics = check_string("let x = 1;\nlet y = ~x;");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message
+            .starts_with("error: unexpected character `~`\n --> 2:9\n"));
+    }
+
+    #[test]
+    fn test_eval_sources() {
+        let sources = vec![
+            ("math.blang", "let square = fn(x) { x * x };"),
+            ("main.blang", "let n = 4;\nsquare(n) + 1"),
+        ];
+        assert_eq!(eval_sources(&sources).unwrap().unwrap().inspect(), "17");
+
+        // Later sources can't be used by earlier ones
+        let sources = vec![
+            ("main.blang", "square(4)"),
+            ("math.blang", "let square = fn(x) { x * x };"),
+        ];
+        assert_eq!(
+            eval_sources(&sources).err().unwrap(),
+            "main.blang: unknown identifier: square"
+        );
+
+        let sources = vec![("a.blang", "let x = 1;"), ("b.blang", "x @ 0")];
+        assert_eq!(
+            eval_sources(&sources).err().unwrap(),
+            "b.blang: error: unexpected character `@`\n --> 1:3\n  |\n1 | x @ 0\n  |   ^"
+        );
+
+        let sources = vec![("a.blang", "let x = 1;"), ("b.blang", "x / 0")];
+        assert_eq!(
+            eval_sources(&sources).err().unwrap(),
+            "b.blang: division by zero"
+        );
+    }
+
+    #[test]
+    fn test_run_files() {
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("blang_lib_{}.blang", std::process::id()));
+        let second = dir.join(format!("blang_main_{}.blang", std::process::id()));
+        let first = first.to_str().unwrap().to_string();
+        let second = second.to_str().unwrap().to_string();
+        fs::write(&first, "let greet = fn(name) { \"hi \" + name };").unwrap();
+        fs::write(&second, "greet(\"bob\")").unwrap();
+
+        let result = run_files(&[first.clone(), second.clone()]);
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+        assert_eq!(result.unwrap().unwrap().inspect(), "\"hi bob\"");
+
+        let missing = run_files(&["/nonexistent/script.blang".to_string()]);
+        assert!(missing
+            .err()
+            .unwrap()
+            .starts_with("/nonexistent/script.blang: failed to read"));
+    }
+
+    #[test]
+    fn test_run_reader() {
+        let input = Cursor::new(b"let x = 5;\nlet y = x * 2;\ny + 1\n");
+        let result = run_reader(input).unwrap();
+        assert_eq!(result.unwrap().inspect(), "11");
+
+        let input = Cursor::new(b"let x = 5;");
+        assert!(run_reader(input).unwrap().is_none());
+
+        let input = Cursor::new(vec![0x31, 0xff, 0xfe]);
+        let error = run_reader(input).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_run_file_args() {
+        let path = std::env::temp_dir().join(format!("blang_args_{}.blang", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, "let name = args[0];\nlen(args) + len(name)").unwrap();
+
+        let args = vec!["hello".to_string(), "world".to_string()];
+        let result = run_file(&path, &args);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap().unwrap().inspect(), "7");
+
+        let result = eval_with_args("args[1]", &args);
+        assert_eq!(result.unwrap().inspect(), "\"world\"");
+        assert_eq!(eval_string("args").unwrap().inspect(), "[]");
+
+        let missing = run_file("/nonexistent/script.blang", &[]);
+        assert_eq!(missing.err().unwrap().kind(), std::io::ErrorKind::NotFound);
+    }
+}
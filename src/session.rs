@@ -0,0 +1,83 @@
+use crate::ast::Parser;
+use crate::diagnostics::syntax_error;
+use crate::lexer::Lexer;
+use crate::program::Program;
+use crate::types::{is_error_option, Object};
+
+// Evaluates snippets of source one after another against the same
+// environment, like the REPL but without any terminal handling.
+pub struct Session {
+    pub program: Program,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        return Session {
+            program: Program::new(vec![]),
+        };
+    }
+
+    // Runs `src`, returning the value of its last statement. A statement that
+    // errors is dropped again, so its bindings don't leak into later snippets.
+    pub fn eval(&mut self, src: &str) -> Option<Box<dyn Object>> {
+        let error = syntax_error(src);
+        if error.is_some() {
+            return error;
+        }
+
+        let lexer = Lexer::new(src.to_string());
+        let mut parser = Parser::new(lexer);
+
+        let mut result: Option<Box<dyn Object>> = None;
+        for statement in parser.parse() {
+            self.program.extend(vec![statement]);
+            result = self.program.eval();
+
+            if is_error_option(&result) {
+                self.program.walk_back_error();
+                self.program.discard_evaluated();
+                return result;
+            }
+            self.program.discard_evaluated();
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::set_input;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new();
+        assert!(session.eval("let x = 5").is_none());
+        assert_eq!(session.eval("x + 1").unwrap().inspect(), "6");
+
+        assert_eq!(
+            session
+                .eval("let y = x * 2; let z = foo; 3")
+                .unwrap()
+                .inspect(),
+            "unknown identifier: foo"
+        );
+        assert_eq!(session.eval("y").unwrap().inspect(), "10");
+        assert_eq!(
+            session.eval("z").unwrap().inspect(),
+            "unknown identifier: z"
+        );
+
+        let mut other = Session::new();
+        assert_eq!(other.eval("x").unwrap().inspect(), "unknown identifier: x");
+    }
+
+    #[test]
+    fn test_failed_statement_runs_once() {
+        set_input(Box::new(Cursor::new("one\ntwo\nthree\n")));
+        let mut session = Session::new();
+        assert!(session.eval("read_line() + 1").unwrap().is_error());
+        assert_eq!(session.eval("read_line()").unwrap().inspect(), "\"two\"");
+    }
+}
@@ -1,14 +1,174 @@
+use crate::ast::Parser;
+use crate::diagnostics::syntax_error;
 use crate::environment::Environment;
-use crate::program::ProgramNode;
-use crate::token::Token;
-use crate::types::{Boolean, Error, Function, Integer, Object, StringLiteral, Type};
-
+use crate::lexer::lookup_keyword;
+use crate::lexer::Lexer;
+use crate::program::{json_string, ArithmeticMode, EnvUpdates, ProgramNode};
+use crate::token::{Token, TokenType};
+use crate::types::{
+    is_error_option, next_function_id, quote_string, Array, Boolean, Builtin, BuiltinFunction,
+    ComposedFunction, Error, ErrorKind, Function, Hash, Integer, MemoKey, MemoizedFunction, Null,
+    Object, StringLiteral, Type,
+};
+use std::fs;
+use std::rc::Rc;
+
+#[deprecated(note = "use Object::is_error or types::is_error_option")]
+#[allow(clippy::borrowed_box)]
 pub fn is_error(object: Option<&Box<dyn Object>>) -> bool {
-    if object.is_some() {
-        if object.as_ref().unwrap().type_() == Type::ERROR {
-            return true;
+    return object.is_some() && object.unwrap().is_error();
+}
+
+// Only false and null are falsy; every other value is truthy.
+pub fn is_truthy(object: &dyn Object) -> bool {
+    let boolean = object.downcast_ref::<Boolean>();
+    if boolean.is_some() {
+        return boolean.unwrap().value;
+    }
+    return object.type_() != Type::NULL;
+}
+
+// An error when an array or hash of `size` elements or pairs is bigger than
+// `env` allows. Checked before building one, so the limit bounds memory too.
+pub fn check_collection_size(size: usize, env: &Environment) -> Option<Box<dyn Object>> {
+    if size > env.max_collection_size {
+        return Some(Box::new(Error {
+            kind: ErrorKind::CollectionTooLarge,
+            data: None,
+            message: format!(
+                "collection size limit exceeded: {} > {}",
+                size, env.max_collection_size
+            ),
+        }));
+    }
+    return None;
+}
+
+// Calls `function` with already evaluated arguments, using `env` as the scope
+// that the function's parameters are bound into.
+pub fn apply_function(
+    function: &dyn Object,
+    args: Vec<Box<dyn Object>>,
+    mut env: Environment,
+) -> Option<Box<dyn Object>> {
+    let builtin = function.downcast_ref::<Builtin>();
+    if builtin.is_some() {
+        let builtin = builtin.unwrap();
+        if !builtin.arity.accepts(args.len()) {
+            return Some(Box::new(Error {
+                kind: ErrorKind::WrongArgumentCount,
+                data: None,
+                message: format!(
+                    "wrong number of arguments to {}: expected {}, got {}",
+                    builtin.name,
+                    builtin.arity.describe(),
+                    args.len()
+                ),
+            }));
+        }
+        match builtin.function {
+            BuiltinFunction::Plain(function) => return Some(function(args)),
+            BuiltinFunction::WithEnv(function) => return Some(function(args, &env)),
+        }
+    }
+
+    let memoized = function.downcast_ref::<MemoizedFunction>();
+    if memoized.is_some() {
+        let memoized = memoized.unwrap();
+        let key = memoized_key(&args);
+        if key.is_some() {
+            let cached = memoized
+                .cache
+                .borrow()
+                .get(key.as_ref().unwrap())
+                .map(|x| x.get_box());
+            if cached.is_some() {
+                return cached;
+            }
+        }
+
+        let result = apply_function(memoized.function.as_ref(), args, env);
+        if key.is_some() && result.is_some() && !is_error_option(&result) {
+            memoized
+                .cache
+                .borrow_mut()
+                .insert(key.unwrap(), result.as_ref().unwrap().get_box());
+        }
+        return result;
+    }
+
+    let composed = function.downcast_ref::<ComposedFunction>();
+    if composed.is_some() {
+        let composed = composed.unwrap();
+        let inner_result = apply_function(composed.inner.as_ref(), args, env.get_copy());
+        if is_error_option(&inner_result) {
+            return inner_result;
+        }
+        let inner_result: Box<dyn Object> = inner_result.unwrap_or(Box::new(Null {}));
+        return apply_function(composed.outer.as_ref(), vec![inner_result], env);
+    }
+
+    let func = function.downcast_ref::<Function>();
+    if func.is_none() {
+        return Some(Box::new(Error {
+            kind: ErrorKind::NotAFunction,
+            data: None,
+            message: format!("not a function: {:?}", function.type_()),
+        }));
+    }
+    let func = func.unwrap();
+
+    // Constants protect the bindings they were made in, names the call binds
+    // for itself may shadow them
+    env.constants.clear();
+
+    // Bind Parameters, collecting any trailing arguments into a rest parameter
+    let mut args = args.into_iter();
+    for param in &func.parameters {
+        let rest = param.downcast_ref::<RestParameterExpression>();
+        if rest.is_some() {
+            let elements: Vec<Box<dyn Object>> = args.by_ref().collect();
+            env.update(rest.unwrap().value.clone(), Box::new(Array { elements }));
+            break;
+        }
+
+        let arg = args.next();
+        if arg.is_some() {
+            env.update(param.token_literal().unwrap(), arg.unwrap());
+        }
+    }
+
+    return func.body.eval(&mut env);
+}
+
+// Only scalar arguments are hashable; any other argument bypasses the cache.
+fn memoized_key(args: &[Box<dyn Object>]) -> Option<MemoKey> {
+    let mut key: MemoKey = vec![];
+    for arg in args {
+        match arg.type_() {
+            Type::INTEGER | Type::STRING | Type::BOOLEAN | Type::NULL => {
+                key.push((arg.type_(), arg.inspect()));
+            }
+            _ => return None,
         }
     }
+    return Some(key);
+}
+
+// Functions compare by identity rather than by their source.
+fn same_function(left: &dyn Object, right: &dyn Object) -> bool {
+    let left_fn = left.downcast_ref::<Function>();
+    let right_fn = right.downcast_ref::<Function>();
+    if left_fn.is_some() && right_fn.is_some() {
+        return left_fn.unwrap().id == right_fn.unwrap().id;
+    }
+
+    let left_memo = left.downcast_ref::<MemoizedFunction>();
+    let right_memo = right.downcast_ref::<MemoizedFunction>();
+    if left_memo.is_some() && right_memo.is_some() {
+        return Rc::ptr_eq(&left_memo.unwrap().cache, &right_memo.unwrap().cache);
+    }
+
     return false;
 }
 
@@ -42,17 +202,41 @@ impl ProgramNode for LetStatement {
         return self.token.literal.to_owned();
     }
 
-    fn eval(&self, _env: &mut Environment) -> Option<Box<dyn Object>> {
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return None;
     }
 
-    fn update_env(&self, env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
+    fn update_env(&self, env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        let name = self.name.token_literal().unwrap();
+        if env.constants.contains(&name) {
+            return Err(Box::new(Error {
+                kind: ErrorKind::ConstantReassigned,
+                data: None,
+                message: format!("cannot reassign constant: {}", name),
+            }));
+        }
+
         let result = self.value.eval(env);
+        if is_error_option(&result) {
+            return Err(result.unwrap());
+        }
+
+        if self.token.token_type == TokenType::CONST {
+            env.constants.insert(name);
+        }
         if result.is_some() {
-            return Some(vec![(self.name.to_string(), result.unwrap())]);
+            return Ok(Some(vec![(
+                self.name.token_literal().unwrap(),
+                result.unwrap(),
+            )]));
         }
 
-        return None;
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.name.as_ref(), self.value.as_ref()];
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -64,6 +248,76 @@ impl ProgramNode for LetStatement {
     }
 }
 
+// `let name = value in body` binds `name` only while evaluating `body`, in a
+// copy of the environment, so the binding never leaks out.
+pub struct LetInExpression {
+    pub token: Token,
+    pub name: Box<dyn ProgramNode>,
+    pub value: Box<dyn ProgramNode>,
+    pub body: Box<dyn ProgramNode>,
+}
+
+impl LetInExpression {
+    pub fn new(
+        token: Token,
+        name: Box<dyn ProgramNode>,
+        value: Box<dyn ProgramNode>,
+        body: Box<dyn ProgramNode>,
+    ) -> LetInExpression {
+        return LetInExpression {
+            token,
+            name,
+            value,
+            body,
+        };
+    }
+}
+
+impl ProgramNode for LetInExpression {
+    fn to_string(&self) -> String {
+        return format!(
+            "let {} = {} in {}",
+            self.name.to_string(),
+            self.value.to_string(),
+            self.body.to_string()
+        );
+    }
+
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let value = self.value.eval(env);
+        if is_error_option(&value) {
+            return value;
+        }
+
+        let mut local_env = env.get_copy();
+        let value: Box<dyn Object> = value.unwrap_or(Box::new(Null {}));
+        local_env.update(self.name.token_literal().unwrap(), value);
+        return self.body.eval(&mut local_env);
+    }
+
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.name.as_ref(), self.value.as_ref(), self.body.as_ref()];
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(LetInExpression {
+            token: self.token.clone(),
+            name: self.name.get_copy(),
+            value: self.value.get_copy(),
+            body: self.body.get_copy(),
+        });
+    }
+}
+
 pub struct ReturnStatement {
     token: Token,
     value: Box<dyn ProgramNode>,
@@ -87,10 +341,15 @@ impl ProgramNode for ReturnStatement {
         return self.token.literal.to_owned();
     }
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return self.value.eval(env);
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.value.as_ref()];
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -101,6 +360,84 @@ impl ProgramNode for ReturnStatement {
     }
 }
 
+// `import "path"` runs the file at `path`, relative to the working directory,
+// and brings its top-level bindings into the importing environment.
+pub struct ImportStatement {
+    token: Token,
+    pub path: String,
+}
+
+impl ImportStatement {
+    pub fn new(token: Token, path: String) -> ImportStatement {
+        return ImportStatement { token, path };
+    }
+
+    fn import_error(&self, message: String) -> Box<dyn Object> {
+        return Box::new(Error {
+            kind: ErrorKind::ImportFailed,
+            data: None,
+            message: format!("cannot import {}: {}", self.path, message),
+        });
+    }
+}
+
+impl ProgramNode for ImportStatement {
+    fn to_string(&self) -> String {
+        return format!("import {}", quote_string(&self.path));
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        return None;
+    }
+    fn update_env(&self, env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        let source = fs::read_to_string(&self.path);
+        if source.is_err() {
+            return Err(self.import_error(source.err().unwrap().to_string()));
+        }
+
+        // The same file can be reached through different relative paths
+        let canonical = fs::canonicalize(&self.path)
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or(self.path.clone());
+        if env.imports.contains(&canonical) {
+            return Err(self.import_error("import cycle".to_string()));
+        }
+
+        let source = source.unwrap();
+        let error = syntax_error(&source);
+        if error.is_some() {
+            return Err(self.import_error(error.unwrap().inspect()));
+        }
+        let mut parser = Parser::new(Lexer::new(source));
+        let block = BlockStatement::new(self.token.clone(), parser.parse());
+
+        let mut local_env = env.get_copy();
+        local_env.imports.push(canonical);
+        let updates = block.update_env(&mut local_env);
+        if updates.is_err() {
+            let error = updates.err().unwrap();
+            // Errors from nested imports already say where they came from
+            if error.downcast_ref::<Error>().unwrap().kind == ErrorKind::ImportFailed {
+                return Err(error);
+            }
+            return Err(self.import_error(error.inspect()));
+        }
+
+        env.constants.extend(local_env.constants);
+        return updates;
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(ImportStatement {
+            token: self.token.clone(),
+            path: self.path.clone(),
+        });
+    }
+}
+
 pub struct ExpressionStatement {
     token: Token,
     pub expression: Box<dyn ProgramNode>,
@@ -119,13 +456,25 @@ impl ProgramNode for ExpressionStatement {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"expression\":{}}}",
+            self.node_type(),
+            self.expression.to_json()
+        );
+    }
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return self.expression.eval(env);
     }
-    fn update_env(&self, env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
+    fn update_env(&self, env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
         return self.expression.update_env(env);
     }
 
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.expression.as_ref()];
+    }
+
     fn get_copy(&self) -> Box<dyn ProgramNode> {
         return Box::new(ExpressionStatement {
             token: self.token.clone(),
@@ -143,58 +492,122 @@ impl BlockStatement {
     pub fn new(token: Token, statements: Vec<Box<dyn ProgramNode>>) -> BlockStatement {
         return BlockStatement { token, statements };
     }
-}
 
-impl ProgramNode for BlockStatement {
-    fn to_string(&self) -> String {
-        let mut str: Vec<String> = Vec::new();
-        for statement in &self.statements {
-            str.push(format!("{};", statement.to_string()));
-        }
-        return str.join(" ");
-    }
-    fn token_literal(&self) -> Option<String> {
-        return self.token.literal.to_owned();
-    }
-    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+    // Blocks nested through if expressions are walked with an explicit stack of
+    // (block, statement index) frames rather than by recursing, so deeply nested
+    // programs don't grow the native stack. Also says whether a return was
+    // reached, which ends a loop running the block.
+    pub fn eval_statements(&self, env: &mut Environment) -> (Option<Box<dyn Object>>, bool) {
+        let mut frames: Vec<(&BlockStatement, usize)> = vec![(self, 0)];
         let mut result: Option<Box<dyn Object>> = None;
-        for statement in &self.statements {
-            result = statement.eval(env);
+        let mut returned = false;
+
+        loop {
+            let (block, idx) = frames[frames.len() - 1];
+            if idx >= block.statements.len() {
+                // The finished block is the value of the parent's if statement
+                frames.pop();
+                if frames.len() == 0 {
+                    return (result, returned);
+                }
+            } else {
+                let timed_out = env.check_deadline();
+                if timed_out.is_some() {
+                    return (timed_out, false);
+                }
+
+                let statement = &block.statements[idx];
+                let if_expr = as_if_expression(statement.as_ref());
+                if if_expr.is_some() {
+                    env.record_eval(statement.node_type());
+                    env.record_eval(if_expr.unwrap().node_type());
+                    let branch = match if_expr.unwrap().select_branch(env) {
+                        Ok(branch) => branch,
+                        Err(error) => return (Some(error), false),
+                    };
+                    if branch.is_some() {
+                        let nested = branch.unwrap().downcast_ref::<BlockStatement>();
+                        if nested.is_some() {
+                            env.record_eval(nested.unwrap().node_type());
+                            frames.push((nested.unwrap(), 0));
+                            continue;
+                        }
+                        result = branch.unwrap().eval(env);
+                    } else {
+                        result = Some(Box::new(Null {}));
+                    }
+                } else {
+                    result = statement.eval(env);
+                }
+
+                // A loop in the statement returned, so the rest is skipped too
+                if env.returning {
+                    return (result, true);
+                }
+            }
+
+            // Finish the statement the top frame is pointing at
+            let top = frames.len() - 1;
+            let (block, idx) = frames[top];
+            let statement = &block.statements[idx];
 
             if statement.token_literal().unwrap() == "return" {
-                return result;
+                // A return only leaves the block it appears in
+                frames[top].1 = block.statements.len();
+                returned = true;
+                continue;
             }
 
-            if is_error(result.as_ref()) {
-                return result;
+            if is_error_option(&result) {
+                return (result, false);
             }
 
-            let env_update = statement.update_env(env);
+            let env_update = match statement.update_env(env) {
+                Ok(env_update) => env_update,
+                Err(error) => return (Some(error), false),
+            };
             if env_update.is_some() {
                 let unwrapped = env_update.unwrap();
                 for update in unwrapped {
                     env.update(update.0, update.1);
                 }
             }
+
+            frames[top].1 += 1;
         }
+    }
+}
 
-        return result;
+impl ProgramNode for BlockStatement {
+    fn to_string(&self) -> String {
+        let mut str: Vec<String> = Vec::new();
+        for statement in &self.statements {
+            str.push(format!("{};", statement.to_string()));
+        }
+        return str.join(" ");
     }
-    fn update_env(&self, env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        return self.eval_statements(env).0;
+    }
+    fn update_env(&self, env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
         let mut updates: Vec<(String, Box<dyn Object>)> = vec![];
         let mut result: Option<Box<dyn Object>>;
         for statement in &self.statements {
             result = statement.eval(env);
 
-            if statement.token_literal().unwrap() == "return" {
-                return Some(updates);
+            if statement.token_literal().unwrap() == "return" || env.returning {
+                return Ok(Some(updates));
             }
 
-            if is_error(result.as_ref()) {
-                return Some(updates);
+            if is_error_option(&result) {
+                return Err(result.unwrap());
             }
 
-            let env_update = statement.update_env(env);
+            let env_update = statement.update_env(env)?;
             if env_update.is_some() {
                 let unwrapped = env_update.unwrap();
                 for update in unwrapped {
@@ -204,7 +617,11 @@ impl ProgramNode for BlockStatement {
             }
         }
 
-        return Some(updates);
+        return Ok(Some(updates));
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return self.statements.iter().map(|x| x.as_ref()).collect();
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -214,7 +631,7 @@ impl ProgramNode for BlockStatement {
         }
         return Box::new(BlockStatement {
             token: self.token.clone(),
-            statements: statements,
+            statements,
         });
     }
 }
@@ -231,17 +648,29 @@ impl IdentifierExpression {
 }
 
 impl ProgramNode for IdentifierExpression {
+    // Names that clash with a keyword are written back escaped
     fn to_string(&self) -> String {
+        if lookup_keyword(&self.value).is_some() {
+            return format!("`{}`", self.value);
+        }
         return self.value.clone();
     }
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"value\":{}}}",
+            self.node_type(),
+            json_string(&self.value)
+        );
+    }
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return Some(env.get(&self.value));
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -252,6 +681,47 @@ impl ProgramNode for IdentifierExpression {
     }
 }
 
+pub struct RestParameterExpression {
+    pub token: Token,
+    pub value: String,
+}
+
+impl RestParameterExpression {
+    pub fn new(token: Token, value: String) -> RestParameterExpression {
+        return RestParameterExpression { token, value };
+    }
+}
+
+impl ProgramNode for RestParameterExpression {
+    fn to_string(&self) -> String {
+        return format!("...{}", self.value);
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"value\":{}}}",
+            self.node_type(),
+            json_string(&self.value)
+        );
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        return Some(env.get(&self.value));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(RestParameterExpression {
+            token: self.token.clone(),
+            value: self.value.clone(),
+        });
+    }
+}
+
 pub struct IntegerLiteralExpression {
     token: Token,
     pub value: i64,
@@ -270,16 +740,24 @@ impl ProgramNode for IntegerLiteralExpression {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
-    fn eval(&self, _env: &mut Environment) -> Option<Box<dyn Object>> {
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"value\":{}}}",
+            self.node_type(),
+            self.value
+        );
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return Some(Box::new(Integer { value: self.value }));
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
     }
     fn get_copy(&self) -> Box<dyn ProgramNode> {
         return Box::new(IntegerLiteralExpression {
             token: self.token.clone(),
-            value: self.value.clone(),
+            value: self.value,
         });
     }
 }
@@ -296,22 +774,32 @@ impl StringLiteralExpression {
 }
 
 impl ProgramNode for StringLiteralExpression {
+    // Quoted and escaped so the output can be lexed again
     fn to_string(&self) -> String {
-        return self.string.to_string();
+        return quote_string(&self.string);
     }
 
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
 
-    fn eval(&self, _env: &mut Environment) -> Option<Box<dyn Object>> {
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"value\":{}}}",
+            self.node_type(),
+            json_string(&self.string)
+        );
+    }
+
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return Some(Box::new(StringLiteral {
             value: self.string.clone(),
         }));
     }
 
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -340,17 +828,25 @@ impl ProgramNode for BooleanExpression {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
-    fn eval(&self, _env: &mut Environment) -> Option<Box<dyn Object>> {
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"value\":{}}}",
+            self.node_type(),
+            self.value
+        );
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         return Some(Box::new(Boolean { value: self.value }));
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
         return Box::new(BooleanExpression {
             token: self.token.clone(),
-            value: self.value.clone(),
+            value: self.value,
         });
     }
 }
@@ -378,10 +874,19 @@ impl ProgramNode for PrefixExpression {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"operator\":{},\"right\":{}}}",
+            self.node_type(),
+            json_string(&self.operator),
+            self.right.to_json()
+        );
+    }
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         let right_eval = self.right.eval(env);
         let right_result = right_eval.as_ref().unwrap();
-        if is_error(right_eval.as_ref()) {
+        if is_error_option(&right_eval) {
             return right_eval;
         }
         let right_type = right_result.type_();
@@ -389,40 +894,51 @@ impl ProgramNode for PrefixExpression {
         let op = self.operator.as_str();
         match op {
             "!" => {
-                let res: bool;
-                if right_type == Type::BOOLEAN {
-                    if right_result.downcast_ref::<Boolean>().unwrap().value {
-                        res = false;
-                    } else {
-                        res = true;
-                    }
-                } else if right_type == Type::NULL {
-                    res = false;
-                } else {
-                    res = false;
-                }
-                return Some(Box::new(Boolean { value: res }));
+                return Some(Box::new(Boolean {
+                    value: !is_truthy(right_result.as_ref()),
+                }));
             }
             "-" => {
                 if right_type == Type::INTEGER {
                     let val = right_result.downcast_ref::<Integer>().unwrap().value;
-                    return Some(Box::new(Integer { value: -val }));
+                    let negated = match env.arithmetic_mode {
+                        ArithmeticMode::Checked => val.checked_neg(),
+                        ArithmeticMode::Wrapping => Some(val.wrapping_neg()),
+                    };
+                    if negated.is_none() {
+                        return Some(Box::new(Error {
+                            kind: ErrorKind::IntegerOverflow,
+                            data: None,
+                            message: format!("integer overflow: -{}", val),
+                        }));
+                    }
+                    return Some(Box::new(Integer {
+                        value: negated.unwrap(),
+                    }));
                 } else {
                     return Some(Box::new(Error {
+                        kind: ErrorKind::InvalidOperand,
+                        data: None,
                         message: format!("invalid type: -{:?}", right_type),
                     }));
                 }
             }
             _ => {
                 return Some(Box::new(Error {
+                    kind: ErrorKind::UnknownOperator,
+                    data: None,
                     message: format!("unknown operator: {:?}", op),
                 }));
             }
         }
     }
 
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.right.as_ref()];
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -434,7 +950,53 @@ impl ProgramNode for PrefixExpression {
     }
 }
 
-pub struct InfixExpression {
+// Returns None when checked arithmetic overflows.
+fn integer_arithmetic(mode: ArithmeticMode, operator: &str, left: i64, right: i64) -> Option<i64> {
+    match (mode, operator) {
+        (ArithmeticMode::Checked, "+") => return left.checked_add(right),
+        (ArithmeticMode::Checked, "-") => return left.checked_sub(right),
+        (ArithmeticMode::Checked, "*") => return left.checked_mul(right),
+        (ArithmeticMode::Checked, "/") => return left.checked_div(right),
+        (ArithmeticMode::Wrapping, "+") => return Some(left.wrapping_add(right)),
+        (ArithmeticMode::Wrapping, "-") => return Some(left.wrapping_sub(right)),
+        (ArithmeticMode::Wrapping, "*") => return Some(left.wrapping_mul(right)),
+        (ArithmeticMode::Wrapping, "/") => return Some(left.wrapping_div(right)),
+        (_, "**") => return integer_pow(mode, left, right),
+        _ => return None,
+    }
+}
+
+// Exponentiation by squaring, for non-negative exponents of any size.
+fn integer_pow(mode: ArithmeticMode, base: i64, exponent: i64) -> Option<i64> {
+    let mut result: i64 = 1;
+    let mut base = Some(base);
+    let mut exponent = exponent;
+    while exponent > 0 {
+        let current = base?;
+        if exponent % 2 == 1 {
+            result = integer_arithmetic(mode, "*", result, current)?;
+        }
+        exponent /= 2;
+        if exponent > 0 {
+            base = integer_arithmetic(mode, "*", current, current);
+        }
+    }
+    return Some(result);
+}
+
+// Hashes can define arithmetic operators by holding a function under these
+// keys, which is called with the right operand.
+fn operator_method(operator: &str) -> Option<&'static str> {
+    match operator {
+        "+" => return Some("__add__"),
+        "-" => return Some("__sub__"),
+        "*" => return Some("__mul__"),
+        "/" => return Some("__div__"),
+        _ => return None,
+    }
+}
+
+pub struct InfixExpression {
     token: Token,
     pub left: Box<dyn ProgramNode>,
     pub operator: String,
@@ -455,6 +1017,28 @@ impl InfixExpression {
             right,
         };
     }
+
+    // `a ?? b` is `a` unless it is null or an error, in which case `b` is
+    // evaluated instead. A timeout still stops evaluation.
+    fn coalesce(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        let left_eval = self.left.eval(env);
+        if left_eval.is_some() {
+            let left_result = left_eval.unwrap();
+            let error = left_result.downcast_ref::<Error>();
+            if error.is_some() && error.unwrap().kind == ErrorKind::Timeout {
+                return Some(left_result);
+            }
+            if error.is_none() && left_result.type_() != Type::NULL {
+                return Some(left_result);
+            }
+        }
+
+        let right_eval = self.right.eval(env);
+        if right_eval.is_none() {
+            return Some(Box::new(Null {}));
+        }
+        return right_eval;
+    }
 }
 
 impl ProgramNode for InfixExpression {
@@ -469,44 +1053,129 @@ impl ProgramNode for InfixExpression {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.to_owned();
     }
+    fn to_json(&self) -> String {
+        return format!(
+            "{{\"kind\":\"{}\",\"operator\":{},\"left\":{},\"right\":{}}}",
+            self.node_type(),
+            json_string(&self.operator),
+            self.left.to_json(),
+            self.right.to_json()
+        );
+    }
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        if self.operator == "??" {
+            return self.coalesce(env);
+        }
+
         // Check Left
         let left_eval = self.left.eval(env);
-        if is_error(left_eval.as_ref()) {
+        if is_error_option(&left_eval) {
             return left_eval;
         }
         let left_result = left_eval.unwrap();
 
+        // && and || only evaluate the right side when it decides the result
+        if self.operator == "&&" || self.operator == "||" {
+            let left_truthy = is_truthy(left_result.as_ref());
+            if (self.operator == "&&") != left_truthy {
+                return Some(Box::new(Boolean { value: left_truthy }));
+            }
+
+            let right_eval = self.right.eval(env);
+            if is_error_option(&right_eval) {
+                return right_eval;
+            }
+            let right_truthy = right_eval.is_some() && is_truthy(right_eval.unwrap().as_ref());
+            return Some(Box::new(Boolean {
+                value: right_truthy,
+            }));
+        }
+
         // Check right
         let right_eval = self.right.eval(env);
-        if is_error(right_eval.as_ref()) {
+        if is_error_option(&right_eval) {
             return right_eval;
         }
         let right_result = right_eval.unwrap();
 
+        let hash = left_result.downcast_ref::<Hash>();
+        let dunder = operator_method(self.operator.as_str());
+        if hash.is_some() && dunder.is_some() {
+            let method = hash.unwrap().get(&StringLiteral {
+                value: dunder.unwrap().to_string(),
+            });
+            if method.is_none() {
+                return Some(Box::new(Error {
+                    kind: ErrorKind::UnknownOperator,
+                    data: None,
+                    message: format!(
+                        "unsupported operator {} for HASH: no {} key",
+                        self.operator,
+                        dunder.unwrap()
+                    ),
+                }));
+            }
+            return apply_function(method.unwrap(), vec![right_result], env.get_copy());
+        }
+
         if left_result.type_() == Type::INTEGER && right_result.type_() == Type::INTEGER {
             let left_int = left_result.downcast_ref::<Integer>().unwrap();
             let right_int = right_result.downcast_ref::<Integer>().unwrap();
 
             let res: Option<Box<dyn Object>> = match self.operator.as_str() {
-                "-" => Some(Box::new(Integer {
-                    value: left_int.value - right_int.value,
-                })),
-                "+" => Some(Box::new(Integer {
-                    value: left_int.value + right_int.value,
-                })),
-                "/" => Some(Box::new(Integer {
-                    value: left_int.value / right_int.value,
-                })),
-                "*" => Some(Box::new(Integer {
-                    value: left_int.value * right_int.value,
-                })),
+                "+" | "-" | "*" | "/" | "**" => {
+                    if self.operator == "/" && right_int.value == 0 {
+                        return Some(Box::new(Error {
+                            kind: ErrorKind::DivisionByZero,
+                            data: None,
+                            message: "division by zero".to_string(),
+                        }));
+                    }
+                    // There are no floats to hold a fractional result
+                    if self.operator == "**" && right_int.value < 0 {
+                        return Some(Box::new(Error {
+                            kind: ErrorKind::InvalidOperand,
+                            data: None,
+                            message: format!(
+                                "negative exponent: {} ** {} has no integer result",
+                                left_int.value, right_int.value
+                            ),
+                        }));
+                    }
+
+                    let value = integer_arithmetic(
+                        env.arithmetic_mode,
+                        self.operator.as_str(),
+                        left_int.value,
+                        right_int.value,
+                    );
+                    if value.is_none() {
+                        return Some(Box::new(Error {
+                            kind: ErrorKind::IntegerOverflow,
+                            data: None,
+                            message: format!(
+                                "integer overflow: {} {} {}",
+                                left_int.value, self.operator, right_int.value
+                            ),
+                        }));
+                    }
+                    Some(Box::new(Integer {
+                        value: value.unwrap(),
+                    }))
+                }
                 ">" => Some(Box::new(Boolean {
                     value: left_int.value > right_int.value,
                 })),
                 "<" => Some(Box::new(Boolean {
                     value: left_int.value < right_int.value,
                 })),
+                ">=" => Some(Box::new(Boolean {
+                    value: left_int.value >= right_int.value,
+                })),
+                "<=" => Some(Box::new(Boolean {
+                    value: left_int.value <= right_int.value,
+                })),
                 "==" => Some(Box::new(Boolean {
                     value: left_int.value == right_int.value,
                 })),
@@ -530,8 +1199,68 @@ impl ProgramNode for InfixExpression {
                 _ => None,
             };
             return res;
+        } else if left_result.type_() == Type::STRING
+            && right_result.type_() == Type::STRING
+            && self.operator == "+"
+        {
+            let left_str = &left_result.downcast_ref::<StringLiteral>().unwrap().value;
+            let right_str = &right_result.downcast_ref::<StringLiteral>().unwrap().value;
+            return Some(Box::new(StringLiteral {
+                value: format!("{}{}", left_str, right_str),
+            }));
+        } else if left_result.type_() == Type::ARRAY
+            && right_result.type_() == Type::ARRAY
+            && self.operator == "+"
+        {
+            // A new array, leaving both operands as they were
+            let left_array = left_result.downcast_ref::<Array>().unwrap();
+            let right_array = right_result.downcast_ref::<Array>().unwrap();
+            let too_large =
+                check_collection_size(left_array.elements.len() + right_array.elements.len(), env);
+            if too_large.is_some() {
+                return too_large;
+            }
+            let mut elements: Vec<Box<dyn Object>> = vec![];
+            for element in left_array.elements.iter().chain(&right_array.elements) {
+                elements.push(element.get_box());
+            }
+            return Some(Box::new(Array { elements }));
+        } else if left_result.type_() == Type::STRING
+            && right_result.type_() == Type::STRING
+            && ["==", "!=", "<", ">", "<=", ">="].contains(&self.operator.as_str())
+        {
+            // Strings order lexicographically by their characters
+            let left_str = &left_result.downcast_ref::<StringLiteral>().unwrap().value;
+            let right_str = &right_result.downcast_ref::<StringLiteral>().unwrap().value;
+            let value = match self.operator.as_str() {
+                "==" => left_str == right_str,
+                "!=" => left_str != right_str,
+                "<" => left_str < right_str,
+                ">" => left_str > right_str,
+                "<=" => left_str <= right_str,
+                _ => left_str >= right_str,
+            };
+            return Some(Box::new(Boolean { value }));
+        } else if (left_result.type_() == Type::NULL || right_result.type_() == Type::NULL)
+            && (self.operator == "==" || self.operator == "!=")
+        {
+            // Null is only ever equal to null
+            let equal = left_result.type_() == right_result.type_();
+            return Some(Box::new(Boolean {
+                value: if self.operator == "==" { equal } else { !equal },
+            }));
+        } else if left_result.type_() == Type::FUNCTION
+            && right_result.type_() == Type::FUNCTION
+            && (self.operator == "==" || self.operator == "!=")
+        {
+            let same = same_function(left_result.as_ref(), right_result.as_ref());
+            return Some(Box::new(Boolean {
+                value: if self.operator == "==" { same } else { !same },
+            }));
         } else {
             return Some(Box::new(Error {
+                kind: ErrorKind::TypeMismatch,
+                data: None,
                 message: format!(
                     "type mismatch: {:?} {} {:?}",
                     left_result.type_(),
@@ -541,8 +1270,12 @@ impl ProgramNode for InfixExpression {
             }));
         }
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.left.as_ref(), self.right.as_ref()];
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
@@ -555,6 +1288,96 @@ impl ProgramNode for InfixExpression {
     }
 }
 
+// A braced block used as a value. Its bindings live in a copy of the
+// environment, and it evaluates to its last statement, or null.
+pub struct BlockExpression {
+    token: Token,
+    pub block: Box<dyn ProgramNode>,
+}
+
+impl BlockExpression {
+    pub fn new(token: Token, block: Box<dyn ProgramNode>) -> BlockExpression {
+        return BlockExpression { token, block };
+    }
+}
+
+impl ProgramNode for BlockExpression {
+    fn to_string(&self) -> String {
+        return format!("{{ {} }}", self.block.to_string());
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let mut local_env = env.get_copy();
+        let result = self.block.eval(&mut local_env);
+        if result.is_none() {
+            return Some(Box::new(Null {}));
+        }
+        return result;
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.block.as_ref()];
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(BlockExpression {
+            token: self.token.clone(),
+            block: self.block.get_copy(),
+        });
+    }
+}
+
+// Keeps the parentheses the user wrote. Infix and prefix expressions already
+// render their own, so only other expressions are wrapped again.
+pub struct GroupedExpression {
+    token: Token,
+    pub expression: Box<dyn ProgramNode>,
+}
+
+impl GroupedExpression {
+    pub fn new(token: Token, expression: Box<dyn ProgramNode>) -> GroupedExpression {
+        return GroupedExpression { token, expression };
+    }
+}
+
+impl ProgramNode for GroupedExpression {
+    fn to_string(&self) -> String {
+        if self.expression.downcast_ref::<InfixExpression>().is_some()
+            || self.expression.downcast_ref::<PrefixExpression>().is_some()
+        {
+            return self.expression.to_string();
+        }
+        return format!("({})", self.expression.to_string());
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        return self.expression.eval(env);
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.expression.as_ref()];
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(GroupedExpression {
+            token: self.token.clone(),
+            expression: self.expression.get_copy(),
+        });
+    }
+}
+
 pub struct IfExpression {
     token: Token,
     pub condition: Box<dyn ProgramNode>,
@@ -578,19 +1401,61 @@ impl IfExpression {
     }
 }
 
+impl IfExpression {
+    // Evaluates the condition and picks the branch to run, if any.
+    pub fn select_branch(
+        &self,
+        env: &mut Environment,
+    ) -> Result<Option<&dyn ProgramNode>, Box<dyn Object>> {
+        let condition_result = self.condition.eval(env);
+        if is_error_option(&condition_result) {
+            return Err(condition_result.unwrap());
+        }
+
+        let use_first = condition_result.is_some() && is_truthy(condition_result.unwrap().as_ref());
+
+        if use_first {
+            return Ok(Some(self.consequence.as_ref()));
+        } else if self.alternative.is_some() {
+            return Ok(Some(self.alternative.as_ref().unwrap().as_ref()));
+        } else {
+            return Ok(None);
+        }
+    }
+}
+
+fn as_if_expression(statement: &dyn ProgramNode) -> Option<&IfExpression> {
+    let expression_statement = statement.downcast_ref::<ExpressionStatement>();
+    if expression_statement.is_some() {
+        return expression_statement
+            .unwrap()
+            .expression
+            .downcast_ref::<IfExpression>();
+    }
+    return statement.downcast_ref::<IfExpression>();
+}
+
 impl ProgramNode for IfExpression {
     fn to_string(&self) -> String {
         if self.alternative.is_some() {
             let alt = self.alternative.as_ref().unwrap();
+            if alt.downcast_ref::<IfExpression>().is_some() {
+                return format!(
+                    "if ({}) {{ {} }} else {}",
+                    self.condition.to_string(),
+                    self.consequence.to_string(),
+                    alt.to_string()
+                );
+            }
             return format!(
-                "if {} {} else {}",
+                "if ({}) {{ {} }} else {{ {} }}",
                 self.condition.to_string(),
                 self.consequence.to_string(),
                 alt.to_string()
             );
         } else {
             return format!(
-                "if {} {}",
+                "if ({}) {{ {} }}",
                 self.condition.to_string(),
                 self.consequence.to_string()
             );
@@ -600,49 +1465,554 @@ impl ProgramNode for IfExpression {
         return self.token.literal.to_owned();
     }
     fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let branch = match self.select_branch(env) {
+            Ok(branch) => branch,
+            Err(error) => return Some(error),
+        };
+        if branch.is_some() {
+            return branch.unwrap().eval(env);
+        }
+        // An untaken if still has a value, so it can be bound or passed on
+        return Some(Box::new(Null {}));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        let mut children = vec![self.condition.as_ref(), self.consequence.as_ref()];
+        if self.alternative.is_some() {
+            children.push(self.alternative.as_ref().unwrap().as_ref());
+        }
+        return children;
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(IfExpression {
+            token: self.token.clone(),
+            condition: self.condition.get_copy(),
+            consequence: self.consequence.get_copy(),
+            alternative: self.alternative.as_ref().map(|x| x.get_copy()),
+        });
+    }
+}
+
+// `while (cond) { body }` checks the condition before every run of the body,
+// `do { body } while (cond)` only after, so the body runs at least once. The
+// body shares the enclosing environment, so its lets carry between iterations.
+// A return in the body ends the loop and the rest of the function around it.
+pub struct WhileExpression {
+    token: Token,
+    pub condition: Box<dyn ProgramNode>,
+    pub body: Box<dyn ProgramNode>,
+    pub check_first: bool,
+}
+
+impl WhileExpression {
+    pub fn new(
+        token: Token,
+        condition: Box<dyn ProgramNode>,
+        body: Box<dyn ProgramNode>,
+        check_first: bool,
+    ) -> WhileExpression {
+        return WhileExpression {
+            token,
+            condition,
+            body,
+            check_first,
+        };
+    }
+
+    fn should_continue(&self, env: &mut Environment) -> Result<bool, Box<dyn Object>> {
         let condition_result = self.condition.eval(env);
-        if is_error(condition_result.as_ref()) {
-            return condition_result;
+        if is_error_option(&condition_result) {
+            return Err(condition_result.unwrap());
         }
+        return Ok(condition_result.is_some() && is_truthy(condition_result.unwrap().as_ref()));
+    }
+}
 
-        let use_first: bool;
-        if condition_result.is_some() {
-            let unwrapped = condition_result.unwrap();
-            if &unwrapped.type_() == &Type::BOOLEAN {
-                use_first = unwrapped.downcast_ref::<Boolean>().unwrap().value;
+impl ProgramNode for WhileExpression {
+    fn to_string(&self) -> String {
+        if self.check_first {
+            return format!(
+                "while ({}) {{ {} }}",
+                self.condition.to_string(),
+                self.body.to_string()
+            );
+        }
+        return format!(
+            "do {{ {} }} while ({})",
+            self.body.to_string(),
+            self.condition.to_string()
+        );
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let mut run_body = !self.check_first;
+        loop {
+            let timed_out = env.check_deadline();
+            if timed_out.is_some() {
+                return timed_out;
+            }
+
+            if !run_body {
+                match self.should_continue(env) {
+                    Ok(true) => {}
+                    Ok(false) => return Some(Box::new(Null {})),
+                    Err(error) => return Some(error),
+                }
+            }
+            run_body = false;
+
+            let block = self.body.downcast_ref::<BlockStatement>();
+            let (result, returned) = if block.is_some() {
+                env.record_eval(block.unwrap().node_type());
+                block.unwrap().eval_statements(env)
             } else {
-                use_first = true;
+                (self.body.eval(env), false)
+            };
+            if returned || env.returning {
+                // Leave the loop and the rest of the function it is in
+                env.returning = true;
+                return result;
+            }
+            if is_error_option(&result) {
+                return result;
             }
-        } else {
-            use_first = false;
         }
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
 
-        if use_first {
-            let res = self.consequence.eval(env);
-            return res;
-        } else if self.alternative.is_some() {
-            let unwrapped = self.alternative.as_ref().unwrap();
-            return unwrapped.eval(env);
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.condition.as_ref(), self.body.as_ref()];
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(WhileExpression {
+            token: self.token.clone(),
+            condition: self.condition.get_copy(),
+            body: self.body.get_copy(),
+            check_first: self.check_first,
+        });
+    }
+}
+
+// `try { body } catch (e) { handler }` runs the handler when the body fails,
+// with `e` bound to the value passed to `throw`, or to the message of a
+// runtime error. The handler gets its own environment, like a let-in body, so
+// `e` does not outlive it. Timeouts are not caught.
+pub struct TryExpression {
+    token: Token,
+    pub body: Box<dyn ProgramNode>,
+    pub name: Box<dyn ProgramNode>,
+    pub handler: Box<dyn ProgramNode>,
+}
+
+impl TryExpression {
+    pub fn new(
+        token: Token,
+        body: Box<dyn ProgramNode>,
+        name: Box<dyn ProgramNode>,
+        handler: Box<dyn ProgramNode>,
+    ) -> TryExpression {
+        return TryExpression {
+            token,
+            body,
+            name,
+            handler,
+        };
+    }
+}
+
+impl ProgramNode for TryExpression {
+    fn to_string(&self) -> String {
+        return format!(
+            "try {{ {} }} catch ({}) {{ {} }}",
+            self.body.to_string(),
+            self.name.to_string(),
+            self.handler.to_string()
+        );
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let result = self.body.eval(env);
+        if !is_error_option(&result) {
+            if result.is_none() {
+                return Some(Box::new(Null {}));
+            }
+            return result;
+        }
+
+        let error = result.unwrap();
+        let error = error.downcast_ref::<Error>().unwrap();
+        if error.kind == ErrorKind::Timeout {
+            return Some(error.get_box());
+        }
+
+        // Thrown values are caught as they were thrown, other errors as
+        // their message
+        let caught: Box<dyn Object> = if error.data.is_some() {
+            error.data.as_ref().unwrap().get_box()
         } else {
-            return None;
+            Box::new(StringLiteral {
+                value: error.message.clone(),
+            })
+        };
+        let mut local_env = env.get_copy();
+        local_env.update(self.name.token_literal().unwrap(), caught);
+        let handled = self.handler.eval(&mut local_env);
+        if handled.is_none() {
+            return Some(Box::new(Null {}));
         }
+        return handled;
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![
+            self.body.as_ref(),
+            self.name.as_ref(),
+            self.handler.as_ref(),
+        ];
     }
 
     fn get_copy(&self) -> Box<dyn ProgramNode> {
-        let alt: Option<Box<dyn ProgramNode>>;
-        if self.alternative.is_some() {
-            alt = Some(self.alternative.as_ref().unwrap().get_copy());
-        } else {
-            alt = None;
+        return Box::new(TryExpression {
+            token: self.token.clone(),
+            body: self.body.get_copy(),
+            name: self.name.get_copy(),
+            handler: self.handler.get_copy(),
+        });
+    }
+}
+
+// `throw value` raises an error whose message is the value as `puts` would
+// print it. The value itself goes along as the error's data, so `catch` gets
+// back exactly what was thrown.
+pub struct ThrowExpression {
+    token: Token,
+    pub value: Box<dyn ProgramNode>,
+}
+
+impl ThrowExpression {
+    pub fn new(token: Token, value: Box<dyn ProgramNode>) -> ThrowExpression {
+        return ThrowExpression { token, value };
+    }
+}
+
+impl ProgramNode for ThrowExpression {
+    fn to_string(&self) -> String {
+        return format!("throw {}", self.value.to_string());
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let value = self.value.eval(env);
+        if is_error_option(&value) {
+            return value;
         }
-        return Box::new(IfExpression {
+
+        let value: Box<dyn Object> = value.unwrap_or(Box::new(Null {}));
+        return Some(Box::new(Error {
+            kind: ErrorKind::Thrown,
+            message: value.display(),
+            data: Some(value),
+        }));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.value.as_ref()];
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(ThrowExpression {
             token: self.token.clone(),
-            condition: self.condition.get_copy(),
-            consequence: self.consequence.get_copy(),
-            alternative: alt,
+            value: self.value.get_copy(),
+        });
+    }
+}
+
+pub enum MatchPattern {
+    Wildcard,
+    Value(Box<dyn ProgramNode>),
+    // Both ends are included
+    Range(Box<dyn ProgramNode>, Box<dyn ProgramNode>),
+}
+
+pub struct MatchArm {
+    pub pattern: MatchPattern,
+    pub value: Box<dyn ProgramNode>,
+}
+
+impl MatchArm {
+    fn pattern_string(&self) -> String {
+        match &self.pattern {
+            MatchPattern::Wildcard => return "_".to_string(),
+            MatchPattern::Value(value) => return value.to_string(),
+            MatchPattern::Range(low, high) => {
+                return format!("{}..{}", low.to_string(), high.to_string());
+            }
+        }
+    }
+
+    fn matches(
+        &self,
+        subject: &dyn Object,
+        env: &mut Environment,
+    ) -> Result<bool, Box<dyn Object>> {
+        match &self.pattern {
+            MatchPattern::Wildcard => return Ok(true),
+            MatchPattern::Value(value) => {
+                let value = value.eval(env).unwrap();
+                if value.is_error() {
+                    return Err(value);
+                }
+                let key = Hash::hash_key(subject);
+                return Ok(key.is_some() && key == Hash::hash_key(value.as_ref()));
+            }
+            MatchPattern::Range(low, high) => {
+                let subject = subject.downcast_ref::<Integer>();
+                if subject.is_none() {
+                    return Ok(false);
+                }
+                let mut bounds = vec![];
+                for bound in [low, high] {
+                    let bound = bound.eval(env).unwrap();
+                    if bound.is_error() {
+                        return Err(bound);
+                    }
+                    let integer = bound.downcast_ref::<Integer>();
+                    if integer.is_none() {
+                        return Err(Box::new(Error {
+                            kind: ErrorKind::TypeMismatch,
+                            data: None,
+                            message: format!(
+                                "range bound must be INTEGER, got {:?}",
+                                bound.type_()
+                            ),
+                        }));
+                    }
+                    bounds.push(integer.unwrap().value);
+                }
+                let value = subject.unwrap().value;
+                return Ok(bounds[0] <= value && value <= bounds[1]);
+            }
+        }
+    }
+
+    fn get_copy(&self) -> MatchArm {
+        let pattern = match &self.pattern {
+            MatchPattern::Wildcard => MatchPattern::Wildcard,
+            MatchPattern::Value(value) => MatchPattern::Value(value.get_copy()),
+            MatchPattern::Range(low, high) => MatchPattern::Range(low.get_copy(), high.get_copy()),
+        };
+        return MatchArm {
+            pattern,
+            value: self.value.get_copy(),
+        };
+    }
+}
+
+// `match (x) { 1 => a, 2..5 => b, _ => c }` evaluates `x` once and gives the
+// value of the first arm whose pattern it matches.
+pub struct MatchExpression {
+    token: Token,
+    pub subject: Box<dyn ProgramNode>,
+    pub arms: Vec<MatchArm>,
+}
+
+impl MatchExpression {
+    pub fn new(
+        token: Token,
+        subject: Box<dyn ProgramNode>,
+        arms: Vec<MatchArm>,
+    ) -> MatchExpression {
+        return MatchExpression {
+            token,
+            subject,
+            arms,
+        };
+    }
+}
+
+impl ProgramNode for MatchExpression {
+    fn to_string(&self) -> String {
+        let arms: Vec<String> = self
+            .arms
+            .iter()
+            .map(|arm| format!("{} => {}", arm.pattern_string(), arm.value.to_string()))
+            .collect();
+        return format!(
+            "match ({}) {{ {} }}",
+            self.subject.to_string(),
+            arms.join(", ")
+        );
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let subject = self.subject.eval(env);
+        if is_error_option(&subject) {
+            return subject;
+        }
+        let subject: Box<dyn Object> = subject.unwrap_or(Box::new(Null {}));
+
+        for arm in &self.arms {
+            match arm.matches(subject.as_ref(), env) {
+                Ok(true) => return arm.value.eval(env),
+                Ok(false) => {}
+                Err(error) => return Some(error),
+            }
+        }
+        return Some(Box::new(Error {
+            kind: ErrorKind::NoMatchingArm,
+            data: None,
+            message: format!("no match arm for {}", subject.inspect()),
+        }));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        let mut children = vec![self.subject.as_ref()];
+        for arm in &self.arms {
+            match &arm.pattern {
+                MatchPattern::Wildcard => {}
+                MatchPattern::Value(value) => children.push(value.as_ref()),
+                MatchPattern::Range(low, high) => {
+                    children.push(low.as_ref());
+                    children.push(high.as_ref());
+                }
+            }
+            children.push(arm.value.as_ref());
+        }
+        return children;
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(MatchExpression {
+            token: self.token.clone(),
+            subject: self.subject.get_copy(),
+            arms: self.arms.iter().map(|arm| arm.get_copy()).collect(),
+        });
+    }
+}
+
+pub enum TemplatePart {
+    Text(String),
+    Expression(Box<dyn ProgramNode>),
+}
+
+// `Hello ${name}`: the text with each expression's value spliced in, shown
+// the way `puts` would print it.
+pub struct TemplateExpression {
+    token: Token,
+    pub parts: Vec<TemplatePart>,
+}
+
+impl TemplateExpression {
+    pub fn new(token: Token, parts: Vec<TemplatePart>) -> TemplateExpression {
+        return TemplateExpression { token, parts };
+    }
+}
+
+impl ProgramNode for TemplateExpression {
+    // Escaped so the output can be lexed again
+    fn to_string(&self) -> String {
+        let mut template = String::from("`");
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => {
+                    for ch in text.chars() {
+                        match ch {
+                            '`' | '$' | '\\' => {
+                                template.push('\\');
+                                template.push(ch);
+                            }
+                            '\n' => template.push_str("\\n"),
+                            '\t' => template.push_str("\\t"),
+                            ch => template.push(ch),
+                        }
+                    }
+                }
+                TemplatePart::Expression(expression) => {
+                    template.push_str(&format!("${{{}}}", expression.to_string()));
+                }
+            }
+        }
+        template.push('`');
+        return template;
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let mut value = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => value.push_str(text),
+                TemplatePart::Expression(expression) => {
+                    let result = expression.eval(env);
+                    if is_error_option(&result) {
+                        return result;
+                    }
+                    let result: Box<dyn Object> = result.unwrap_or(Box::new(Null {}));
+                    value.push_str(&result.display());
+                }
+            }
+        }
+        return Some(Box::new(StringLiteral { value }));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        let mut children: Vec<&dyn ProgramNode> = vec![];
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(_) => {}
+                TemplatePart::Expression(expression) => children.push(expression.as_ref()),
+            }
+        }
+        return children;
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        let parts = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => TemplatePart::Text(text.clone()),
+                TemplatePart::Expression(expression) => {
+                    TemplatePart::Expression(expression.get_copy())
+                }
+            })
+            .collect();
+        return Box::new(TemplateExpression {
+            token: self.token.clone(),
+            parts,
         });
     }
 }
@@ -682,21 +2052,30 @@ impl ProgramNode for FunctionLiteralExpression {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.clone();
     }
-    fn eval(&self, _env: &mut Environment) -> Option<Box<dyn Object>> {
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
         let mut params: Vec<Box<dyn ProgramNode>> = vec![];
         for param in &self.parameters {
             params.push(param.get_copy());
         }
         return Some(Box::new(Function {
+            id: next_function_id(),
             body: self.body.get_copy(),
-            env: _env.get_copy(),
+            env: env.get_copy(),
             parameters: params,
         }));
     }
 
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        todo!();
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
     }
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        let mut children: Vec<&dyn ProgramNode> =
+            self.parameters.iter().map(|x| x.as_ref()).collect();
+        children.push(self.body.as_ref());
+        return children;
+    }
+
     fn get_copy(&self) -> Box<dyn ProgramNode> {
         let mut params: Vec<Box<dyn ProgramNode>> = vec![];
         for param in &self.parameters {
@@ -710,6 +2089,244 @@ impl ProgramNode for FunctionLiteralExpression {
     }
 }
 
+pub struct HashLiteralExpression {
+    token: Token,
+    pub pairs: Vec<(Box<dyn ProgramNode>, Box<dyn ProgramNode>)>,
+}
+
+impl HashLiteralExpression {
+    pub fn new(
+        token: Token,
+        pairs: Vec<(Box<dyn ProgramNode>, Box<dyn ProgramNode>)>,
+    ) -> HashLiteralExpression {
+        return HashLiteralExpression { token, pairs };
+    }
+}
+
+impl ProgramNode for HashLiteralExpression {
+    fn to_string(&self) -> String {
+        return format!(
+            "{{{}}}",
+            self.pairs
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k.to_string(), v.to_string()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let too_large = check_collection_size(self.pairs.len(), env);
+        if too_large.is_some() {
+            return too_large;
+        }
+        let mut hash = Hash::new();
+        for (key_node, value_node) in &self.pairs {
+            let key = key_node.eval(env);
+            if is_error_option(&key) {
+                return key;
+            }
+            let key: Box<dyn Object> = key.unwrap_or(Box::new(Null {}));
+
+            let value = value_node.eval(env);
+            if is_error_option(&value) {
+                return value;
+            }
+            let value: Box<dyn Object> = value.unwrap_or(Box::new(Null {}));
+
+            let key_type = key.type_();
+            if !hash.insert(key, value) {
+                return Some(Box::new(Error {
+                    kind: ErrorKind::UnusableHashKey,
+                    data: None,
+                    message: format!("unusable as hash key: {:?}", key_type),
+                }));
+            }
+        }
+        return Some(Box::new(hash));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        let mut children: Vec<&dyn ProgramNode> = vec![];
+        for (key, value) in &self.pairs {
+            children.push(key.as_ref());
+            children.push(value.as_ref());
+        }
+        return children;
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        let mut pairs: Vec<(Box<dyn ProgramNode>, Box<dyn ProgramNode>)> = vec![];
+        for (key, value) in &self.pairs {
+            pairs.push((key.get_copy(), value.get_copy()));
+        }
+        return Box::new(HashLiteralExpression {
+            token: self.token.clone(),
+            pairs,
+        });
+    }
+}
+
+pub struct ArrayLiteralExpression {
+    token: Token,
+    pub elements: Vec<Box<dyn ProgramNode>>,
+}
+
+impl ArrayLiteralExpression {
+    pub fn new(token: Token, elements: Vec<Box<dyn ProgramNode>>) -> ArrayLiteralExpression {
+        return ArrayLiteralExpression { token, elements };
+    }
+}
+
+impl ProgramNode for ArrayLiteralExpression {
+    fn to_string(&self) -> String {
+        return format!(
+            "[{}]",
+            self.elements
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let too_large = check_collection_size(self.elements.len(), env);
+        if too_large.is_some() {
+            return too_large;
+        }
+        let mut elements: Vec<Box<dyn Object>> = vec![];
+        for element in &self.elements {
+            let value = element.eval(env);
+            if is_error_option(&value) {
+                return value;
+            }
+            elements.push(value.unwrap_or(Box::new(Null {})));
+        }
+        return Some(Box::new(Array { elements }));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return self.elements.iter().map(|x| x.as_ref()).collect();
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        let mut elements: Vec<Box<dyn ProgramNode>> = vec![];
+        for element in &self.elements {
+            elements.push(element.get_copy());
+        }
+        return Box::new(ArrayLiteralExpression {
+            token: self.token.clone(),
+            elements,
+        });
+    }
+}
+
+pub struct IndexExpression {
+    token: Token,
+    pub left: Box<dyn ProgramNode>,
+    pub index: Box<dyn ProgramNode>,
+}
+
+impl IndexExpression {
+    pub fn new(
+        token: Token,
+        left: Box<dyn ProgramNode>,
+        index: Box<dyn ProgramNode>,
+    ) -> IndexExpression {
+        return IndexExpression { token, left, index };
+    }
+}
+
+impl ProgramNode for IndexExpression {
+    fn to_string(&self) -> String {
+        return format!("({}[{}])", self.left.to_string(), self.index.to_string());
+    }
+    fn token_literal(&self) -> Option<String> {
+        return self.token.literal.to_owned();
+    }
+    // Missing array positions and hash keys evaluate to null.
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let left = self.left.eval(env);
+        if is_error_option(&left) {
+            return left;
+        }
+        let left: Box<dyn Object> = left.unwrap_or(Box::new(Null {}));
+
+        let index = self.index.eval(env);
+        if is_error_option(&index) {
+            return index;
+        }
+        let index: Box<dyn Object> = index.unwrap_or(Box::new(Null {}));
+
+        let array = left.downcast_ref::<Array>();
+        let position = index.downcast_ref::<Integer>();
+        if array.is_some() && position.is_some() {
+            let elements = &array.unwrap().elements;
+            let position = position.unwrap().value;
+            if position < 0 || position as usize >= elements.len() {
+                return Some(Box::new(Null {}));
+            }
+            return Some(elements[position as usize].get_box());
+        }
+
+        let hash = left.downcast_ref::<Hash>();
+        if hash.is_some() {
+            if Hash::hash_key(index.as_ref()).is_none() {
+                return Some(Box::new(Error {
+                    kind: ErrorKind::UnusableHashKey,
+                    data: None,
+                    message: format!("unusable as hash key: {:?}", index.type_()),
+                }));
+            }
+
+            let value = hash.unwrap().get(index.as_ref());
+            if value.is_none() {
+                return Some(Box::new(Null {}));
+            }
+            return Some(value.unwrap().get_box());
+        }
+
+        return Some(Box::new(Error {
+            kind: ErrorKind::UnknownOperator,
+            data: None,
+            message: format!(
+                "index operator not supported: {:?}[{:?}]",
+                left.type_(),
+                index.type_()
+            ),
+        }));
+    }
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        return vec![self.left.as_ref(), self.index.as_ref()];
+    }
+
+    fn get_copy(&self) -> Box<dyn ProgramNode> {
+        return Box::new(IndexExpression {
+            token: self.token.clone(),
+            left: self.left.get_copy(),
+            index: self.index.get_copy(),
+        });
+    }
+}
+
 pub struct CallExpression {
     token: Token,
     pub function: Box<dyn ProgramNode>,
@@ -745,29 +2362,37 @@ impl ProgramNode for CallExpression {
     fn token_literal(&self) -> Option<String> {
         return self.token.literal.clone();
     }
-    fn eval(&self, _env: &mut Environment) -> Option<Box<dyn Object>> {
-        let mut scoped_env = _env.get_copy();
+    fn eval(&self, env: &mut Environment) -> Option<Box<dyn Object>> {
+        env.record_eval(self.node_type());
+        let mut scoped_env = env.get_copy();
 
         // Get Function Object
         let og_fn = self.function.eval(&mut scoped_env).unwrap();
-        let og_fn_un = og_fn.downcast_ref::<Function>().unwrap();
+        if og_fn.is_error() {
+            return Some(og_fn);
+        }
 
         // Evaluate Arguments
-        for idx in 0..self.arguments.len() {
-            let eval_ = self.arguments[idx].eval(&mut scoped_env).unwrap();
-            scoped_env.update(og_fn_un.parameters[idx].token_literal().unwrap(), eval_);
+        let mut args: Vec<Box<dyn Object>> = vec![];
+        for argument in &self.arguments {
+            let arg = argument.eval(&mut scoped_env);
+            if is_error_option(&arg) {
+                return arg;
+            }
+            args.push(arg.unwrap_or(Box::new(Null {})));
         }
 
-        let unwrapped = self.function.eval(&mut scoped_env).unwrap();
-        let fn_ = unwrapped.downcast_ref::<Function>().unwrap();
-
-        let result = fn_.body.eval(&mut scoped_env);
-
-        return result;
+        return apply_function(og_fn.as_ref(), args, scoped_env);
     }
-    fn update_env(&self, _env: &mut Environment) -> Option<Vec<(String, Box<dyn Object>)>> {
-        return None;
+    fn update_env(&self, _env: &mut Environment) -> Result<EnvUpdates, Box<dyn Object>> {
+        return Ok(None);
+    }
+    fn children(&self) -> Vec<&dyn ProgramNode> {
+        let mut children = vec![self.function.as_ref()];
+        children.extend(self.arguments.iter().map(|x| x.as_ref()));
+        return children;
     }
+
     fn get_copy(&self) -> Box<dyn ProgramNode> {
         let mut args: Vec<Box<dyn ProgramNode>> = vec![];
         for arg in &self.arguments {
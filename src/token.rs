@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Hash, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TokenType {
     ILLEGAL,
@@ -11,37 +13,70 @@ pub enum TokenType {
     MINUS,
     SLASH,
     ASTERISK,
+    POWER,
     LT,
     GT,
+    LTE,
+    GTE,
     BANG,
+    PIPE,
+    AND,
+    OR,
+    COALESCE,
 
     EQ,
     NEQ,
 
     COMMA,
+    COLON,
+    ELLIPSIS,
+    DOTDOT,
+    ARROW,
     SEMICOLON,
 
     LPAREN,
     RPAREN,
     LBRACE,
     RBRACE,
+    LBRACKET,
+    RBRACKET,
 
     FUNCTION,
     LET,
+    CONST,
+    IN,
     IF,
     ELSE,
+    THEN,
     RETURN,
+    IMPORT,
+    WHILE,
+    DO,
+    TRY,
+    CATCH,
+    THROW,
+    MATCH,
 
     TRUE,
     FALSE,
 
     STRING,
+    TEMPLATE,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+// `span` holds the start and end byte offsets of the token in the source. It
+// is left out of equality, so tokens from different places still compare equal.
+#[derive(Clone, Debug, Eq)]
 pub struct Token {
     pub token_type: TokenType,
     pub literal: Option<String>,
+    pub span: (usize, usize),
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        return self.token_type == other.token_type && self.literal == other.literal;
+    }
 }
 
 impl Token {
@@ -52,15 +87,43 @@ impl Token {
             return Token {
                 token_type,
                 literal: Some(lit),
+                span: (0, 0),
             };
         } else {
             return Token {
                 token_type,
                 literal: None,
+                span: (0, 0),
             };
         }
     }
 }
 
+// Tokens without a literal, like EOF, print their type as a placeholder.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.literal.is_some() {
+            return write!(f, "{}", self.literal.as_ref().unwrap());
+        }
+        return write!(f, "<{:?}>", self.token_type);
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_display() {
+        assert_eq!(Token::new(TokenType::EOF, None).to_string(), "<EOF>");
+        assert_eq!(
+            Token::new(TokenType::ILLEGAL, None).to_string(),
+            "<ILLEGAL>"
+        );
+        assert_eq!(Token::new(TokenType::PIPE, Some("|>")).to_string(), "|>");
+        assert_eq!(
+            Token::new(TokenType::ILLEGAL, Some("bad input")).to_string(),
+            "bad input"
+        );
+    }
+}
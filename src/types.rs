@@ -1,10 +1,13 @@
 extern crate downcast_rs;
 use crate::environment::Environment;
 use crate::program::ProgramNode;
-use crate::statements::{BlockStatement, IdentifierExpression};
 use downcast_rs::{impl_downcast, Downcast};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum Type {
     INTEGER,
     BOOLEAN,
@@ -12,12 +15,46 @@ pub enum Type {
     ERROR,
     FUNCTION,
     STRING,
+    ARRAY,
+    HASH,
+    BUILTIN,
+}
+
+impl Type {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Type::INTEGER => "INTEGER",
+            Type::BOOLEAN => "BOOLEAN",
+            Type::NULL => "NULL",
+            Type::ERROR => "ERROR",
+            Type::FUNCTION => "FUNCTION",
+            Type::STRING => "STRING",
+            Type::ARRAY => "ARRAY",
+            Type::HASH => "HASH",
+            Type::BUILTIN => "BUILTIN",
+        };
+    }
 }
 
 pub trait Object: Downcast {
     fn type_(&self) -> Type;
     fn inspect(&self) -> String;
     fn get_box(&self) -> Box<dyn Object>;
+
+    fn is_error(&self) -> bool {
+        return self.type_() == Type::ERROR;
+    }
+
+    // The text shown when printing a value for people rather than debugging
+    // it. Only strings differ, printing without quotes or escapes.
+    fn display(&self) -> String {
+        return self.inspect();
+    }
+}
+
+// True when an evaluation produced a value and that value is an error.
+pub fn is_error_option(object: &Option<Box<dyn Object>>) -> bool {
+    return object.is_some() && object.as_ref().unwrap().is_error();
 }
 
 impl_downcast!(Object);
@@ -59,11 +96,31 @@ pub struct StringLiteral {
     pub value: String,
 }
 
+// Wraps `value` in quotes, escaping it the way the lexer reads escapes back.
+pub fn quote_string(value: &str) -> String {
+    let mut escaped = String::new();
+    for ch in value.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            _ if ch.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            _ => escaped.push(ch),
+        }
+    }
+    return format!("\"{}\"", escaped);
+}
+
 impl Object for StringLiteral {
     fn type_(&self) -> Type {
         return Type::STRING;
     }
     fn inspect(&self) -> String {
+        return quote_string(&self.value);
+    }
+    fn display(&self) -> String {
         return self.value.clone();
     }
     fn get_box(&self) -> Box<dyn Object> {
@@ -73,6 +130,111 @@ impl Object for StringLiteral {
     }
 }
 
+pub struct Array {
+    pub elements: Vec<Box<dyn Object>>,
+}
+
+impl Object for Array {
+    fn type_(&self) -> Type {
+        return Type::ARRAY;
+    }
+    fn inspect(&self) -> String {
+        return format!(
+            "[{}]",
+            self.elements
+                .iter()
+                .map(|x| x.inspect())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    fn get_box(&self) -> Box<dyn Object> {
+        let mut elements: Vec<Box<dyn Object>> = vec![];
+        for element in &self.elements {
+            elements.push(element.get_box());
+        }
+        return Box::new(Array { elements });
+    }
+}
+
+pub type HashKey = (Type, String);
+
+// Pairs are kept in insertion order, with `index` mapping each key to its pair.
+pub struct Hash {
+    pub pairs: Vec<(Box<dyn Object>, Box<dyn Object>)>,
+    pub index: HashMap<HashKey, usize>,
+}
+
+impl Hash {
+    pub fn new() -> Hash {
+        return Hash {
+            pairs: vec![],
+            index: HashMap::new(),
+        };
+    }
+
+    // Only scalar values can be used as keys.
+    pub fn hash_key(key: &dyn Object) -> Option<HashKey> {
+        match key.type_() {
+            Type::INTEGER | Type::STRING | Type::BOOLEAN => {
+                return Some((key.type_(), key.inspect()));
+            }
+            _ => return None,
+        }
+    }
+
+    // Inserts or replaces the value for `key`, returning false if the key
+    // cannot be hashed.
+    pub fn insert(&mut self, key: Box<dyn Object>, value: Box<dyn Object>) -> bool {
+        let hash_key = Hash::hash_key(key.as_ref());
+        if hash_key.is_none() {
+            return false;
+        }
+
+        let hash_key = hash_key.unwrap();
+        let existing = self.index.get(&hash_key);
+        if existing.is_some() {
+            self.pairs[*existing.unwrap()].1 = value;
+        } else {
+            self.index.insert(hash_key, self.pairs.len());
+            self.pairs.push((key, value));
+        }
+        return true;
+    }
+
+    pub fn get(&self, key: &dyn Object) -> Option<&dyn Object> {
+        let hash_key = Hash::hash_key(key)?;
+        let idx = self.index.get(&hash_key)?;
+        return Some(self.pairs[*idx].1.as_ref());
+    }
+}
+
+impl Object for Hash {
+    fn type_(&self) -> Type {
+        return Type::HASH;
+    }
+    fn inspect(&self) -> String {
+        return format!(
+            "{{{}}}",
+            self.pairs
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k.inspect(), v.inspect()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    fn get_box(&self) -> Box<dyn Object> {
+        let mut pairs: Vec<(Box<dyn Object>, Box<dyn Object>)> = vec![];
+        for (key, value) in &self.pairs {
+            pairs.push((key.get_box(), value.get_box()));
+        }
+        return Box::new(Hash {
+            pairs,
+            index: self.index.clone(),
+        });
+    }
+}
+
 pub struct Null {}
 
 impl Object for Null {
@@ -87,7 +249,33 @@ impl Object for Null {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ErrorKind {
+    TypeMismatch,
+    InvalidOperand,
+    UnknownOperator,
+    UndefinedIdentifier,
+    DivisionByZero,
+    IntegerOverflow,
+    NotAFunction,
+    WrongArgumentCount,
+    InvalidArgument,
+    UnusableHashKey,
+    AssertionFailed,
+    Timeout,
+    Thrown,
+    NoMatchingArm,
+    ConstantReassigned,
+    ImportFailed,
+    SyntaxError,
+    CollectionTooLarge,
+}
+
+// `kind` is for matching on programmatically, `message` is what gets shown.
 pub struct Error {
+    pub kind: ErrorKind,
+    // The value given to `throw`, handed to `catch` instead of the message
+    pub data: Option<Box<dyn Object>>,
     pub message: String,
 }
 
@@ -101,12 +289,23 @@ impl Object for Error {
 
     fn get_box(&self) -> Box<dyn Object> {
         return Box::new(Error {
+            kind: self.kind,
+            data: self.data.as_ref().map(|x| x.get_box()),
             message: self.message.clone(),
         });
     }
 }
 
+static NEXT_FUNCTION_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Every evaluated function literal gets its own id. Copies keep the id, so two
+// functions are equal only when they come from the same evaluation.
+pub fn next_function_id() -> usize {
+    return NEXT_FUNCTION_ID.fetch_add(1, Ordering::Relaxed);
+}
+
 pub struct Function {
+    pub id: usize,
     pub parameters: Vec<Box<dyn ProgramNode>>,
     pub body: Box<dyn ProgramNode>,
     pub env: Environment,
@@ -127,9 +326,171 @@ impl Object for Function {
             params.push(param.get_copy());
         }
         return Box::new(Function {
+            id: self.id,
             parameters: params,
             body: self.body.get_copy(),
             env: self.env.get_copy(),
         });
     }
 }
+
+pub type MemoKey = Vec<(Type, String)>;
+
+// Wraps a function with a cache of results keyed by its arguments. Copies made
+// through get_box share the same cache, so every binding of a memoized function
+// benefits from earlier calls.
+// The cache is the only state values share, and a cached result can hold the
+// memoized function itself, so inspect must never walk into the cache.
+pub struct MemoizedFunction {
+    pub function: Box<dyn Object>,
+    pub cache: Rc<RefCell<HashMap<MemoKey, Box<dyn Object>>>>,
+}
+
+impl Object for MemoizedFunction {
+    fn type_(&self) -> Type {
+        return Type::FUNCTION;
+    }
+
+    fn inspect(&self) -> String {
+        return format!("memoized {}", self.function.inspect());
+    }
+
+    fn get_box(&self) -> Box<dyn Object> {
+        return Box::new(MemoizedFunction {
+            function: self.function.get_box(),
+            cache: Rc::clone(&self.cache),
+        });
+    }
+}
+
+// The result of compose(outer, inner): calling it applies `inner` to the
+// arguments and then `outer` to that result.
+pub struct ComposedFunction {
+    pub outer: Box<dyn Object>,
+    pub inner: Box<dyn Object>,
+}
+
+impl Object for ComposedFunction {
+    fn type_(&self) -> Type {
+        return Type::FUNCTION;
+    }
+
+    fn inspect(&self) -> String {
+        return "composed function".to_string();
+    }
+
+    fn get_box(&self) -> Box<dyn Object> {
+        return Box::new(ComposedFunction {
+            outer: self.outer.get_box(),
+            inner: self.inner.get_box(),
+        });
+    }
+}
+
+pub type PlainBuiltin = fn(Vec<Box<dyn Object>>) -> Box<dyn Object>;
+pub type EnvBuiltin = fn(Vec<Box<dyn Object>>, &Environment) -> Box<dyn Object>;
+
+#[derive(Clone, Copy)]
+pub enum BuiltinFunction {
+    Plain(PlainBuiltin),
+    // Builtins that also read the caller's environment, e.g. to inspect its
+    // bindings or draw from its random generator
+    WithEnv(EnvBuiltin),
+}
+
+// How many arguments a builtin takes. It is checked before the builtin is
+// called, so builtins can index into their arguments directly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(expected) => return count == *expected,
+            Arity::AtLeast(min) => return count >= *min,
+            Arity::Between(min, max) => return *min <= count && count <= *max,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Arity::Exactly(expected) => return expected.to_string(),
+            Arity::AtLeast(min) => return format!("at least {}", min),
+            Arity::Between(min, max) if *max == min + 1 => return format!("{} or {}", min, max),
+            Arity::Between(min, max) => return format!("{} to {}", min, max),
+        }
+    }
+}
+
+pub struct Builtin {
+    pub name: String,
+    pub arity: Arity,
+    pub function: BuiltinFunction,
+}
+
+impl Object for Builtin {
+    fn type_(&self) -> Type {
+        return Type::BUILTIN;
+    }
+
+    fn inspect(&self) -> String {
+        return format!("builtin function: {}", self.name);
+    }
+
+    fn get_box(&self) -> Box<dyn Object> {
+        return Box::new(Builtin {
+            name: self.name.clone(),
+            arity: self.arity,
+            function: self.function,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::eval_string;
+
+    #[test]
+    fn test_hash_keys_keep_their_type() {
+        let test_inputs = vec![
+            ("let h = {1: \"a\", \"1\": \"b\"}; [len(h), h[1], h[\"1\"]]", "[2, \"a\", \"b\"]"),
+            ("let h = {true: \"t\", 1: \"one\", \"true\": \"s\"}; [len(h), h[true], h[1], h[\"true\"]]", "[3, \"t\", \"one\", \"s\"]"),
+            ("let h = {0: \"zero\"}; [h[false], h[\"0\"], h[0]]", "[null, null, \"zero\"]"),
+            ("{1: \"a\", 1: \"b\"}", "{1: \"b\"}"),
+        ];
+        for test_input in test_inputs {
+            assert_eq!(eval_string(test_input.0).unwrap().inspect(), test_input.1);
+        }
+
+        let one = Integer { value: 1 };
+        let string_one = StringLiteral {
+            value: "1".to_string(),
+        };
+        assert_eq!(Hash::hash_key(&one), Some((Type::INTEGER, "1".to_string())));
+        assert_ne!(Hash::hash_key(&one), Hash::hash_key(&string_one));
+    }
+
+    #[test]
+    fn test_string_inspect() {
+        let string = StringLiteral {
+            value: "line1\nline2".to_string(),
+        };
+        assert_eq!(string.inspect(), "\"line1\\nline2\"");
+        assert_eq!(string.display(), "line1\nline2");
+
+        let string = StringLiteral {
+            value: "say \"hi\"\t\\ \u{7}é".to_string(),
+        };
+        assert_eq!(string.inspect(), "\"say \\\"hi\\\"\\t\\\\ \\u{7}é\"");
+
+        let array = Array {
+            elements: vec![string.get_box(), Box::new(Integer { value: 1 })],
+        };
+        assert_eq!(array.display(), array.inspect());
+    }
+}